@@ -0,0 +1,52 @@
+//! Coarse deadline buckets for timer wheels
+
+use crate::{assert_divides, DwtSystick, Instant};
+
+/// Deadlines of a monotonic at `TIMER_HZ` grouped into buckets at `BUCKET_HZ`.
+///
+/// A timer wheel can keep its deadlines in slots of one bucket, e.g. 1 ms at
+/// `BUCKET_HZ = 1_000`, and only arm the hardware compare for the nearest
+/// slot. Each bucket is `TIMER_HZ / BUCKET_HZ` ticks, which must divide
+/// exactly. Deadlines within the same bucket can't be told apart, so arming
+/// the start of a bucket fires up to one bucket early; arm the exact instant
+/// of its nearest deadline instead if that matters.
+pub struct Buckets<const TIMER_HZ: u32, const BUCKET_HZ: u32>;
+
+impl<const TIMER_HZ: u32, const BUCKET_HZ: u32> Buckets<TIMER_HZ, BUCKET_HZ> {
+    /// The number of ticks in one bucket.
+    pub const TICKS: u32 = {
+        assert_divides::<TIMER_HZ, BUCKET_HZ>();
+        TIMER_HZ / BUCKET_HZ
+    };
+
+    /// The index of the bucket containing `at`, counted from the epoch.
+    ///
+    /// Without the `extend` feature the index restarts at zero when the cycle
+    /// counter wraps, use `buckets_between()` for deadlines spanning a wrap.
+    #[inline(always)]
+    pub fn index(at: Instant<TIMER_HZ>) -> u64 {
+        let ticks: u64 = at.ticks() as _;
+        ticks / Self::TICKS as u64
+    }
+
+    /// The instant at which bucket `index` starts.
+    ///
+    /// Without the `extend` feature this wraps with the cycle counter.
+    #[inline(always)]
+    pub fn start(index: u64) -> Instant<TIMER_HZ> {
+        Instant::<TIMER_HZ>::from_ticks(index.wrapping_mul(Self::TICKS as u64) as _)
+    }
+
+    /// The number of buckets from the one containing `from` to the one
+    /// containing `to`, negative if `to` is earlier.
+    ///
+    /// This is the difference of the bucket indices, e.g. the slot of a
+    /// deadline relative to now. It is correct across cycle counter wraps under
+    /// the same conditions as `cycles_between()`.
+    #[inline(always)]
+    pub fn buckets_between(from: Instant<TIMER_HZ>, to: Instant<TIMER_HZ>) -> i64 {
+        let ticks: u64 = from.ticks() as _;
+        let offset = (ticks % Self::TICKS as u64) as i64;
+        (offset + DwtSystick::<TIMER_HZ>::cycles_between(from, to)).div_euclid(Self::TICKS as i64)
+    }
+}
@@ -0,0 +1,41 @@
+//! Reduced-resolution timestamps for compact storage
+
+use crate::{muldiv, CycleSource, GenericCycleMonotonic};
+
+/// Timestamps of a monotonic at `TIMER_HZ >> SHIFT`, stored as `u32`.
+///
+/// This trades resolution for storage, e.g. for logs of thousands of
+/// timestamps in RAM. A coarse timestamp is the tick count divided by
+/// `2^SHIFT`, truncated to 32 bits, so it wraps every `2^(32 + SHIFT)` ticks.
+/// The real time is recovered to within `2^SHIFT` ticks with `to_ticks()` or
+/// `to_micros()`, which add back the resolution but not the wraps.
+pub struct CoarseMono<const TIMER_HZ: u32, const SHIFT: u32>;
+
+impl<const TIMER_HZ: u32, const SHIFT: u32> CoarseMono<TIMER_HZ, SHIFT> {
+    const VALID: () = assert!(SHIFT < 32, "SHIFT must be less than 32");
+
+    /// The rate of the coarse timestamps.
+    pub const HZ: u32 = {
+        let () = Self::VALID;
+        TIMER_HZ >> SHIFT
+    };
+
+    /// The current coarse timestamp of `mono`.
+    #[inline(always)]
+    pub fn now<S: CycleSource>(mono: &mut GenericCycleMonotonic<S, TIMER_HZ>) -> u32 {
+        let () = Self::VALID;
+        (mono.tick_count() >> SHIFT) as u32
+    }
+
+    /// Convert a coarse timestamp back to monotonic ticks, rounding down.
+    #[inline(always)]
+    pub const fn to_ticks(coarse: u32) -> u64 {
+        (coarse as u64) << SHIFT
+    }
+
+    /// Convert a coarse timestamp to microseconds, rounding down.
+    #[inline(always)]
+    pub const fn to_micros(coarse: u32) -> u64 {
+        muldiv(Self::to_ticks(coarse), 1_000_000, TIMER_HZ)
+    }
+}
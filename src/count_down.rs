@@ -0,0 +1,49 @@
+//! `embedded-hal` 0.2 `CountDown` timer
+
+use crate::{CycleSource, Duration, GenericCycleMonotonic, Instant};
+use rtic_monotonic::Monotonic;
+
+/// `embedded_hal::timer::CountDown` borrowing the monotonic, see
+/// `GenericCycleMonotonic::count_down()`.
+///
+/// `wait()` compares `now()` with the absolute deadline. It does not depend on
+/// the 24-bit SysTick window, so durations longer than one window are fine and
+/// the SysTick interrupt need not be enabled.
+pub struct CountDown<'a, S, const TIMER_HZ: u32> {
+    mono: &'a mut GenericCycleMonotonic<S, TIMER_HZ>,
+    deadline: Instant<TIMER_HZ>,
+}
+
+impl<'a, S: CycleSource, const TIMER_HZ: u32> CountDown<'a, S, TIMER_HZ> {
+    pub(crate) fn new(mono: &'a mut GenericCycleMonotonic<S, TIMER_HZ>) -> Self {
+        let deadline = mono.now();
+        CountDown { mono, deadline }
+    }
+
+    /// The absolute deadline of the running count.
+    #[inline(always)]
+    pub fn deadline(&self) -> Instant<TIMER_HZ> {
+        self.deadline
+    }
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> embedded_hal::timer::CountDown
+    for CountDown<'_, S, TIMER_HZ>
+{
+    type Time = Duration<TIMER_HZ>;
+
+    fn start<T>(&mut self, count: T)
+    where
+        T: Into<Self::Time>,
+    {
+        self.deadline = self.mono.now() + count.into();
+    }
+
+    fn wait(&mut self) -> nb::Result<(), void::Void> {
+        if self.mono.now() >= self.deadline {
+            Ok(())
+        } else {
+            Err(nb::Error::WouldBlock)
+        }
+    }
+}
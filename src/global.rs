@@ -0,0 +1,93 @@
+//! Lock-free access to the time without a monotonic handle
+
+use crate::{cycle_count_raw, DwtSystick, StampedInstant};
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// The tick frequency of the registered monotonic, zero if none is registered.
+static HZ: AtomicU32 = AtomicU32::new(0);
+
+/// The tick count of the last `now()` in units of half a cycle counter period
+/// (`2^31` ticks), with the `extend` feature.
+///
+/// Together with a cycle counter reading less than half a period after that
+/// `now()` this recovers the full count without a 64-bit atomic.
+#[cfg(feature = "extend")]
+static HALF_PERIODS: AtomicU32 = AtomicU32::new(0);
+
+/// Publish the extended tick count of the registered monotonic.
+#[cfg(feature = "extend")]
+#[inline(always)]
+pub(crate) fn publish(ticks: u64) {
+    HALF_PERIODS.store((ticks >> 31) as u32, Ordering::Relaxed);
+}
+
+/// The current time of the monotonic registered with `register_global()`, or
+/// `None` if none is registered.
+///
+/// Like `timestamp_us()` this needs no handle, so code outside the task that
+/// owns the monotonic (e.g. as an RTIC resource) can read the application's
+/// time without borrowing it. The stamp carries the tick rate, so the caller
+/// does not need to know `TIMER_HZ`. The same accuracy conditions as for
+/// `timestamp_us()` apply.
+pub fn now_shared() -> Option<StampedInstant> {
+    let hz = HZ.load(Ordering::Acquire);
+    if hz == 0 {
+        return None;
+    }
+
+    Some(StampedInstant {
+        ticks: shared_ticks(),
+        hz,
+    })
+}
+
+/// The tick count from the cycle counter and the published overflows.
+#[inline(always)]
+pub(crate) fn shared_ticks() -> u64 {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "extend")] {
+            let base = (HALF_PERIODS.load(Ordering::Relaxed) as u64) << 31;
+            base + cycle_count_raw().wrapping_sub(base as u32) as u64
+        } else {
+            cycle_count_raw() as u64
+        }
+    }
+}
+
+/// Microseconds since the epoch of the monotonic registered with
+/// `register_global()`, or zero if none is registered.
+///
+/// This needs no handle, so it can be used where the monotonic can't be
+/// borrowed, e.g. as `defmt::timestamp!("{=u64:us}", timestamp_us())`.
+///
+/// With the `extend` feature the result is only correct if the monotonic's
+/// `now()` was called within the last half cycle counter overflow period, which
+/// the SysTick interrupt ensures. Without it the count wraps with the cycle
+/// counter.
+pub fn timestamp_us() -> u64 {
+    now_shared().map_or(0, |stamp| stamp.rescale(1_000_000))
+}
+
+impl<const TIMER_HZ: u32> DwtSystick<TIMER_HZ> {
+    /// Register this monotonic as the time base of `timestamp_us()` and
+    /// `now_shared()`.
+    ///
+    /// From then on `now()` also publishes the tracked overflows of the
+    /// `extend` feature to a static. Registration can only happen once: later
+    /// calls, e.g. from a second monotonic, return `false` and change nothing.
+    pub fn register_global(&mut self) -> bool {
+        cortex_m::interrupt::free(|_| {
+            if HZ.load(Ordering::Relaxed) != 0 {
+                return false;
+            }
+
+            self.global = true;
+
+            #[cfg(feature = "extend")]
+            publish(self.peek_ticks());
+
+            HZ.store(TIMER_HZ, Ordering::Release);
+            true
+        })
+    }
+}
@@ -1,8 +1,34 @@
 //! # `Monotonic` implementation based on DWT cycle counter and SysTick
+//!
+//! This implements the RTIC 1 `rtic_monotonic::Monotonic` trait. RTIC binds the
+//! SysTick exception handler itself and calls `on_interrupt()` and
+//! `clear_compare_flag()` from it, including the overflow tracking of the
+//! `extend` feature, so no handler needs to be defined by the application.
+//!
+//! ## Panics
+//!
+//! The `new*()` constructors, `from_source()` and `SharedCycleCounter::new()`
+//! panic on a clock mismatch or a missing cycle counter. Firmware that must not
+//! panic uses their `try_*()` variants instead: `try_new()`,
+//! `try_new_keep_count()`, `try_new_polling()`, `try_new_deferred_trace()`,
+//! `try_new_keep_clock_source()`, `try_from_source()`,
+//! `TickedDwtSystick::try_new()` and `SharedCycleCounter::try_new()`. The
+//! `Monotonic` methods (`reset()`, `now()`, `set_compare()`,
+//! `clear_compare_flag()`, `on_interrupt()`), `try_now()`, `try_set_compare()`
+//! and `try_set_compare_after()` then do not panic in release builds.
+//!
+//! The remaining assertions check documented preconditions of
+//! `Periodic::new()`, `arm_dwt_comparator()`, `rescale_after_clock_change()`
+//! and the debug-only `set_time_scale()`. Explicit `panic!()`s are rejected by
+//! `#![deny(clippy::panic)]`.
 
 #![no_std]
+#![deny(clippy::panic)]
 
-use cortex_m::peripheral::{syst::SystClkSource, DCB, DWT, SYST};
+use core::num::NonZeroU32;
+#[cfg(feature = "trace-compares")]
+use cortex_m::peripheral::ITM;
+use cortex_m::peripheral::{syst::SystClkSource, DCB, DWT, SCB, SYST};
 pub use fugit;
 #[cfg(not(feature = "extend"))]
 pub use fugit::ExtU32;
@@ -10,59 +36,1846 @@ pub use fugit::ExtU32;
 pub use fugit::ExtU64;
 use rtic_monotonic::Monotonic;
 
+mod bucket;
+mod coarse;
+#[cfg(feature = "embedded-hal")]
+mod count_down;
+mod global;
+mod mask;
+mod periodic;
+mod reader;
+mod snapshot;
+mod source;
+mod stamp;
+mod ticked;
+mod timestamp;
+pub use bucket::Buckets;
+pub use coarse::CoarseMono;
+#[cfg(feature = "embedded-hal")]
+pub use count_down::CountDown;
+pub use global::{now_shared, timestamp_us};
+pub use mask::SysTickMaskGuard;
+pub use periodic::Periodic;
+pub use reader::TimeReader;
+pub use snapshot::MonoSnapshot;
+pub use source::{CycleSource, DwtSource, SharedCycleCounter, SharedCycleSource};
+pub use stamp::StampedInstant;
+pub use ticked::TickedDwtSystick;
+pub use timestamp::TimestampSource;
+
+const DCB_DEMCR_TRCENA: u32 = 1 << 24;
+
+/// ITM stimulus port receiving the SysTick reload of every `set_compare()` with
+/// the `trace-compares` feature.
+///
+/// The ITM and this port have to be enabled, e.g. by the debugger. Writes are
+/// dropped if the stimulus FIFO is full.
+#[cfg(feature = "trace-compares")]
+pub const TRACE_COMPARES_PORT: usize = 1;
+
+/// Read the cycle counter without borrowing the monotonic.
+///
+/// This allows timestamping e.g. in panic handlers. Without the `extend`
+/// feature this equals the monotonic's tick count. With `extend` it is only the
+/// low word: the high word is tracked by the monotonic and can't be recovered
+/// from the hardware.
+#[inline(always)]
+pub fn cycle_count_raw() -> u32 {
+    DWT::cycle_count()
+}
+
+/// Extend the 64-bit count `last` by a new 32-bit cycle counter reading.
+///
+/// A reading below the low word of `last` is a cycle counter overflow and is
+/// carried into the high word. The count saturates at `u64::MAX` instead of
+/// wrapping back to zero so it stays monotonic.
+#[cfg(feature = "extend")]
+#[inline(always)]
+fn extend(last: u64, now: u32) -> u64 {
+    if now < last as u32 {
+        (last | 0xffff_ffff).saturating_add(1 + now as u64)
+    } else {
+        (last & !0xffff_ffff) | now as u64
+    }
+}
+
+/// Fail compilation unless `TIMER_HZ` is a non-zero multiple of `TICK_HZ`.
+///
+/// A rate derived from the monotonic, e.g. a scheduler tick, only stays in
+/// step with it if the division is exact. The check is evaluated at compile
+/// time wherever this is called, e.g. `const _: () = assert_divides::<TIMER_HZ,
+/// 1_000>();`.
+#[inline(always)]
+pub const fn assert_divides<const TIMER_HZ: u32, const TICK_HZ: u32>() {
+    Divides::<TIMER_HZ, TICK_HZ>::CHECK
+}
+
+/// The compile time check of `assert_divides()`, an associated const so it is
+/// evaluated for every instantiation.
+struct Divides<const TIMER_HZ: u32, const TICK_HZ: u32>;
+
+impl<const TIMER_HZ: u32, const TICK_HZ: u32> Divides<TIMER_HZ, TICK_HZ> {
+    const CHECK: () = assert!(
+        TICK_HZ > 0 && TIMER_HZ % TICK_HZ == 0,
+        "TIMER_HZ must be a multiple of TICK_HZ"
+    );
+}
+
+/// Cycles elapsed from the raw counter sample `start` to `end`, with `wraps`
+/// the number of times the 32-bit counter passed zero in between.
+///
+/// This is `end + wraps * 2^32 - start`. A wrap is counted when the counter
+/// goes from `u32::MAX` to `0`, so `start = 0xffff_fff0`, `end = 0x10` is one
+/// wrap and 32 cycles. Inconsistent input, with `end < start` but no wraps,
+/// returns 0.
+#[inline(always)]
+pub const fn cycles_between_raw(start: u32, end: u32, wraps: u32) -> u64 {
+    ((wraps as u64) << 32 | end as u64).saturating_sub(start as u64)
+}
+
+/// Rounding of the tick conversions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round down, e.g. for elapsed time that must not be overstated.
+    Floor,
+    /// Round to the nearest value, halfway cases up, e.g. for timestamps.
+    Nearest,
+    /// Round up, e.g. for delays that must last at least the given time.
+    Ceil,
+}
+
+/// Compute `value * num / den`, rounding down and saturating at `u64::MAX`.
+///
+/// The result is exact for all inputs without 128-bit arithmetic, which makes
+/// it suitable for custom rescaling against `TIMER_HZ`, e.g.
+/// `muldiv(ticks, 1_000_000, TIMER_HZ)` for microseconds. All tick and time
+/// conversions of the crate use it.
+///
+/// Panics if `den` is zero.
+#[inline(always)]
+pub const fn muldiv(value: u64, num: u32, den: u32) -> u64 {
+    mul_div_rounded(value, num, den, Rounding::Floor)
+}
+
+/// Compute `value * num / den` with the given rounding, saturating at
+/// `u64::MAX`.
+///
+/// The quotient and remainder of `value / den` are scaled separately so no
+/// intermediate product overflows.
+#[inline(always)]
+const fn mul_div_rounded(value: u64, num: u32, den: u32, rounding: Rounding) -> u64 {
+    let (num, den) = (num as u64, den as u64);
+    let rem = value % den * num;
+    let rem = match rounding {
+        Rounding::Floor => rem / den,
+        Rounding::Nearest => (rem + den / 2) / den,
+        Rounding::Ceil => rem.div_ceil(den),
+    };
+    (value / den).saturating_mul(num).saturating_add(rem)
+}
+
+/// The system clock does not match the declared `TIMER_HZ`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockMismatch {
+    /// The declared `TIMER_HZ`.
+    pub expected: u32,
+    /// The system clock that was passed in.
+    pub actual: u32,
+}
+
+/// Error returned by `DwtSystick::try_new()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InitError {
+    /// The system clock does not match `TIMER_HZ`.
+    ClockMismatch(ClockMismatch),
+    /// The DWT has no cycle counter.
+    NoCycleCounter,
+    /// The SysTick reference clock is zero, faster than `TIMER_HZ` or slower
+    /// than `TIMER_HZ / 128`.
+    ReferenceClock,
+}
+
+impl From<ClockMismatch> for InitError {
+    fn from(e: ClockMismatch) -> Self {
+        InitError::ClockMismatch(e)
+    }
+}
+
+/// SysTick reload used by `DwtSystick::self_test()`.
+const SELF_TEST_RELOAD: u32 = 1_000;
+
+/// Failure reported by `DwtSystick::self_test()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestError {
+    /// The cycle counter does not advance.
+    CounterStalled,
+    /// The SysTick did not wrap within the expected number of cycles.
+    CompareTimeout,
+    /// The SysTick wrapped earlier than its reload value allows.
+    CompareEarly,
+}
+
+/// Result of `DwtSystick::clock_health()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockHealth {
+    /// The measured clock is within the tolerance of `TIMER_HZ`.
+    Ok {
+        /// The measured core clock in Hz.
+        measured_hz: u32,
+    },
+    /// The measured clock deviates from `TIMER_HZ` by more than the tolerance.
+    Drifted {
+        /// The measured core clock in Hz.
+        measured_hz: u32,
+    },
+    /// The SysTick has no reference clock or its calibration value is unknown,
+    /// or the reference clock does not run.
+    NoReference,
+}
+
+/// Error returned by `DwtSystick::reload_for_hz()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadError {
+    /// The rate is zero or its period exceeds the 24-bit SysTick.
+    TooSlow,
+    /// The period is shorter than two ticks.
+    TooFast,
+}
+
+/// Error returned by `DwtSystick::try_set_compare()` and
+/// `DwtSystick::try_set_compare_after()`.
+///
+/// The deadline is too far in the future to be reliably told apart from a
+/// deadline in the past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadlineTooFar;
+
 /// DWT and Systick combination implementing `rtic_monotonic::Monotonic`.
+pub type DwtSystick<const TIMER_HZ: u32> = GenericCycleMonotonic<DwtSource, TIMER_HZ>;
+
+/// The `Monotonic::Instant` of all monotonics at `TIMER_HZ`.
+pub(crate) type Instant<const TIMER_HZ: u32> = <DwtSystick<TIMER_HZ> as Monotonic>::Instant;
+
+/// The `Monotonic::Duration` of all monotonics at `TIMER_HZ`.
+pub(crate) type Duration<const TIMER_HZ: u32> = <DwtSystick<TIMER_HZ> as Monotonic>::Duration;
+
+/// Cycle counter and SysTick combination implementing `rtic_monotonic::Monotonic`.
+///
+/// The cycle counter is provided by the `CycleSource` `S`. `DwtSystick` uses the
+/// DWT cycle counter, other free-running 32-bit counters can reuse the overflow
+/// and compare logic with their own source.
 ///
 /// This implementation is tickless. It does not use periodic interrupts to count
 /// "ticks" (like `systick-monotonic`) but only to obtain actual desired compare
 /// events and to manage overflows.
 ///
-/// The frequency of the DWT and SysTick is encoded using the parameter `TIMER_HZ`.
-/// They must be equal.
+/// The frequency of the cycle counter and SysTick is encoded using the parameter
+/// `TIMER_HZ`. They must be equal.
 ///
 /// Note that the SysTick interrupt must not be disabled longer than half the
 /// cycle counter overflow period (typically a couple seconds).
 ///
 /// When the `extend` feature is enabled, the cycle counter width is extended to
-/// `u64` by detecting and counting overflows.
-pub struct DwtSystick<const TIMER_HZ: u32> {
-    dwt: DWT,
+/// `u64` by detecting and counting overflows. The extended count saturates at
+/// `u64::MAX` (after more than 500 years at 1 GHz) rather than wrapping.
+pub struct GenericCycleMonotonic<S, const TIMER_HZ: u32> {
+    source: S,
     systick: SYST,
+    min_reload: u32,
+    max_reload: u32,
+    reference_hz: Option<NonZeroU32>,
+    initial_reload: Option<u32>,
+    armed: u64,
+    pendsv: bool,
+    clock_tolerance_ppm: u32,
+    trim_ppm: i32,
+    checkpoint: u32,
+    keep_count: bool,
+    polling: bool,
+    free_running: bool,
+    started: bool,
+    global: bool,
+    #[cfg(feature = "extend")]
+    last: u64,
+    #[cfg(feature = "extend")]
+    on_overflow: Option<fn()>,
+    #[cfg(feature = "extend")]
+    missed_reloads: u32,
+    #[cfg(feature = "extend")]
+    long_target: Option<u64>,
+    #[cfg(all(feature = "extend", feature = "diagnostics"))]
+    max_now_gap: u32,
+    #[cfg(feature = "diagnostics")]
+    set_compare_cycles: u32,
+    #[cfg(debug_assertions)]
+    time_scale: (u32, u32),
+}
+
+impl<const TIMER_HZ: u32> DwtSystick<TIMER_HZ> {
+    /// Enable the DWT and provide a new `Monotonic` based on DWT and SysTick.
+    ///
+    /// Note that the `sysclk` parameter should come from e.g. the HAL's clock generation function
+    /// so the speed calculated at runtime and the declared speed (generic parameter
+    /// `TIMER_HZ`) can be compared.
+    #[inline(always)]
+    pub fn new(dcb: &mut DCB, dwt: DWT, systick: SYST, sysclk: u32) -> Self {
+        Self::try_new(dcb, dwt, systick, sysclk).unwrap()
+    }
+
+    /// Like `new()` but return an error instead of panicking.
+    ///
+    /// Together with the other non-panicking methods this allows firmware that
+    /// must not panic to use the monotonic. On error the DWT and SysTick are
+    /// dropped, trace may have been enabled.
+    #[inline(always)]
+    pub fn try_new(dcb: &mut DCB, dwt: DWT, systick: SYST, sysclk: u32) -> Result<Self, InitError> {
+        let mut mono = Self::enable(dcb, dwt, systick, sysclk, SystClkSource::Core)?;
+
+        // Clear the cycle counter here so scheduling (`set_compare()`) before `reset()`
+        // works correctly.
+        mono.source.dwt.set_cycle_count(0);
+
+        Ok(mono)
+    }
+
+    /// Like `new()` but defer enabling trace and the DWT to `reset()`.
+    ///
+    /// This keeps the trace subsystem off, and saves its power, until timing
+    /// starts. Unlike `new()` this does not zero the cycle counter, which can't
+    /// be written before trace is enabled. Until `reset()` the time is not
+    /// meaningful and `set_compare()` must not be used. The cycle counter can
+    /// only be detected once trace is enabled: without one the time stays at
+    /// zero, which `verify_cycle_counter()` after `reset()` reports.
+    #[inline(always)]
+    pub fn new_deferred_trace(dcb: &mut DCB, dwt: DWT, systick: SYST, sysclk: u32) -> Self {
+        Self::try_new_deferred_trace(dcb, dwt, systick, sysclk).unwrap()
+    }
+
+    /// Like `new_deferred_trace()` but return an error instead of panicking.
+    pub fn try_new_deferred_trace(
+        dcb: &mut DCB,
+        dwt: DWT,
+        systick: SYST,
+        sysclk: u32,
+    ) -> Result<Self, InitError> {
+        let trace_was_enabled = dcb.demcr.read() & DCB_DEMCR_TRCENA != 0;
+
+        Ok(Self::try_from_source(
+            DwtSource {
+                dwt,
+                trace_was_enabled,
+                trace_pending: true,
+            },
+            systick,
+            sysclk,
+        )?)
+    }
+
+    /// Like `new()` but keep the running cycle count, e.g. across a warm restart.
+    ///
+    /// Neither this nor the later `reset()` zero the cycle counter, so timestamps
+    /// stay continuous with those taken before the restart. With the `extend`
+    /// feature, the low word is seeded from the current count.
+    ///
+    /// RTIC's `zero()` instant is then no longer the `reset()` time but the
+    /// point where the cycle counter originally started. Everything measured
+    /// "since the epoch" (e.g. `seconds()`) counts from there.
+    #[inline(always)]
+    pub fn new_keep_count(dcb: &mut DCB, dwt: DWT, systick: SYST, sysclk: u32) -> Self {
+        Self::try_new_keep_count(dcb, dwt, systick, sysclk).unwrap()
+    }
+
+    /// Like `new_keep_count()` but return an error instead of panicking.
+    pub fn try_new_keep_count(
+        dcb: &mut DCB,
+        dwt: DWT,
+        systick: SYST,
+        sysclk: u32,
+    ) -> Result<Self, InitError> {
+        let mut mono = Self::enable(dcb, dwt, systick, sysclk, SystClkSource::Core)?;
+        mono.keep_count = true;
+
+        #[cfg(feature = "extend")]
+        {
+            mono.last = DWT::cycle_count() as u64;
+        }
+
+        Ok(mono)
+    }
+
+    /// Provide a monotonic that is only polled, e.g. from a super-loop without RTIC.
+    ///
+    /// The cycle counter is cleared and started right away, so `reset()` is not
+    /// needed. The SysTick is never enabled and `set_compare()` does nothing.
+    /// Deadlines are checked with `poll_deadline()` instead.
+    ///
+    /// With the `extend` feature the overflows are then only tracked by
+    /// `now()`, which has to be called at least once per cycle counter overflow
+    /// period.
+    #[inline(always)]
+    pub fn new_polling(dcb: &mut DCB, dwt: DWT, systick: SYST, sysclk: u32) -> Self {
+        Self::try_new_polling(dcb, dwt, systick, sysclk).unwrap()
+    }
+
+    /// Like `new_polling()` but return an error instead of panicking.
+    pub fn try_new_polling(
+        dcb: &mut DCB,
+        dwt: DWT,
+        systick: SYST,
+        sysclk: u32,
+    ) -> Result<Self, InitError> {
+        let mut mono = Self::try_new(dcb, dwt, systick, sysclk)?;
+        mono.start_polling();
+        Ok(mono)
+    }
+
+    /// Like `new()` but keep the SysTick clock source configured by the HAL.
+    ///
+    /// With the core clock as source this behaves like `new()`. With the
+    /// external reference clock, `reference_hz` is its frequency and the SysTick
+    /// reloads are scaled from `TIMER_HZ` cycles to reference clock ticks,
+    /// rounding up. The reference clock must not be slower than `TIMER_HZ / 128`
+    /// so a full SysTick window stays well within the cycle counter period.
+    ///
+    /// Like `new()`, this leaves the SysTick interrupt enable, reload and
+    /// counter enable untouched.
+    ///
+    /// # Panics
+    ///
+    /// Panics like `new()`, and if the SysTick runs from the reference clock and
+    /// `reference_hz` is zero, above `TIMER_HZ` or below `TIMER_HZ / 128`. Use
+    /// `try_new_keep_clock_source()` to get an error instead.
+    pub fn new_keep_clock_source(
+        dcb: &mut DCB,
+        dwt: DWT,
+        systick: SYST,
+        sysclk: u32,
+        reference_hz: u32,
+    ) -> Self {
+        Self::try_new_keep_clock_source(dcb, dwt, systick, sysclk, reference_hz).unwrap()
+    }
+
+    /// Like `new_keep_clock_source()` but return an error instead of panicking.
+    pub fn try_new_keep_clock_source(
+        dcb: &mut DCB,
+        dwt: DWT,
+        mut systick: SYST,
+        sysclk: u32,
+        reference_hz: u32,
+    ) -> Result<Self, InitError> {
+        let clock = systick.get_clock_source();
+        let reference_hz = match clock {
+            SystClkSource::External => Some(Self::check_reference_clock(reference_hz)?),
+            SystClkSource::Core => None,
+        };
+
+        // Like `try_new()` but without switching the SysTick to the core clock
+        // in between.
+        let mut mono = Self::enable(dcb, dwt, systick, sysclk, clock)?;
+        mono.source.dwt.set_cycle_count(0);
+        mono.reference_hz = reference_hz;
+
+        Ok(mono)
+    }
+
+    /// Check the clock, enable trace and the DWT.
+    fn enable(
+        dcb: &mut DCB,
+        dwt: DWT,
+        systick: SYST,
+        sysclk: u32,
+        clock: SystClkSource,
+    ) -> Result<Self, InitError> {
+        Self::check_clock(sysclk)?;
+
+        Ok(Self::build(DwtSource::enable(dcb, dwt)?, systick, clock))
+    }
+
+    /// Like `new()` but taking the system clock as a typed `fugit::HertzU32`.
+    ///
+    /// `TIMER_HZ` is in Hz, so e.g. a clock accidentally given in kHz is caught
+    /// by the type instead of failing the frequency check.
+    #[inline(always)]
+    pub fn new_hz(dcb: &mut DCB, dwt: DWT, systick: SYST, sysclk: fugit::HertzU32) -> Self {
+        Self::new(dcb, dwt, systick, sysclk.raw())
+    }
+
+    /// Whether trace (`DEMCR.TRCENA`) was already enabled before `new()`.
+    #[inline(always)]
+    pub fn trace_was_enabled(&self) -> bool {
+        self.source.trace_was_enabled
+    }
+
+    /// Stop the monotonic and release the DWT and SysTick.
+    ///
+    /// The SysTick counter and interrupt are disabled. The cycle counter and
+    /// trace are only disabled if trace was not already enabled before `new()`
+    /// so other DWT/ITM users are not disrupted.
+    pub fn free(mut self, dcb: &mut DCB) -> (DWT, SYST) {
+        self.systick.disable_interrupt();
+        self.systick.disable_counter();
+
+        (self.source.free(dcb), self.systick)
+    }
+
+    /// Lend the owned `DWT` to `f`, e.g. to set up a comparator.
+    ///
+    /// The closure must not disable or modify the cycle counter: the monotonic
+    /// relies on it running undisturbed.
+    #[inline(always)]
+    pub fn with_dwt<R>(&mut self, f: impl FnOnce(&mut DWT) -> R) -> R {
+        f(&mut self.source.dwt)
+    }
+
+    /// Apply a custom `DWT` setup between construction and `reset()`.
+    ///
+    /// This is `with_dwt()` for board setup before counting starts, e.g.
+    /// enabling PC sampling or the folded-instruction counter alongside
+    /// `CYCCNT`. The closure must not disable the cycle counter. After
+    /// `new_deferred_trace()` trace is only enabled in `reset()` and DWT writes
+    /// made here may be ignored.
+    #[inline(always)]
+    pub fn configure_dwt(&mut self, f: impl FnOnce(&mut DWT)) {
+        self.with_dwt(f)
+    }
+
+    /// Program DWT comparator `comp` to signal a debug event when the cycle
+    /// counter reaches `at`, independent of the SysTick compare.
+    ///
+    /// This gives a second, cycle exact deadline without another timer. Only
+    /// the low word of `at` is matched, so it has to be less than one counter
+    /// period (`2^32` ticks) in the future. On ARMv7-M only comparator 0
+    /// supports cycle count matching and the number of comparators is given by
+    /// `DWT::num_comp()`.
+    ///
+    /// The event is taken as a DebugMonitor exception if `DEMCR.MON_EN` (bit
+    /// 16) is set and no debugger has enabled halting debug, which halts the
+    /// core instead. The handler has to read the comparator's `FUNCTION`
+    /// register, e.g. through `with_dwt()`, to clear `MATCHED`.
+    ///
+    /// Panics if `comp` is out of range.
+    pub fn arm_dwt_comparator(&mut self, at: <Self as Monotonic>::Instant, comp: u8) {
+        // FUNCTION: CYCMATCH, generate a watchpoint debug event.
+        const CYCMATCH_WATCHPOINT: u32 = 1 << 7 | 0b0100;
+
+        assert!(comp < DWT::num_comp(), "no such DWT comparator");
+        let at: u32 = self.unscaled(at.ticks() as _) as _;
+        let c = &self.source.dwt.c[comp as usize];
+
+        // NOTE(unsafe): the comparator is disabled while it is reconfigured
+        unsafe {
+            c.function.write(0);
+            c.comp.write(at);
+            c.mask.write(0);
+            c.function.write(CYCMATCH_WATCHPOINT);
+        }
+    }
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> GenericCycleMonotonic<S, TIMER_HZ> {
+    /// Provide a new `Monotonic` based on `source` and SysTick.
+    ///
+    /// `sysclk` is compared against the declared `TIMER_HZ` as in
+    /// `DwtSystick::new()`. The source is started by `reset()`.
+    #[inline(always)]
+    pub fn from_source(source: S, systick: SYST, sysclk: u32) -> Self {
+        Self::check_clock(sysclk).unwrap();
+        Self::build(source, systick, SystClkSource::Core)
+    }
+
+    /// Like `from_source()` but return an error instead of panicking.
+    #[inline(always)]
+    pub fn try_from_source(source: S, systick: SYST, sysclk: u32) -> Result<Self, ClockMismatch> {
+        Self::check_clock(sysclk)?;
+        Ok(Self::build(source, systick, SystClkSource::Core))
+    }
+
+    fn build(source: S, mut systick: SYST, clock: SystClkSource) -> Self {
+        systick.set_clock_source(clock);
+
+        // We do not start the counters here but in `reset()`.
+
+        Self::init(source, systick)
+    }
+
+    /// Set up the state without touching any registers.
+    fn init(source: S, systick: SYST) -> Self {
+        GenericCycleMonotonic {
+            source,
+            systick,
+            min_reload: 1,
+            max_reload: 0xff_ffff,
+            reference_hz: None,
+            initial_reload: None,
+            armed: 0,
+            pendsv: false,
+            clock_tolerance_ppm: 10_000,
+            trim_ppm: 0,
+            checkpoint: 0,
+            keep_count: false,
+            polling: false,
+            free_running: false,
+            started: false,
+            global: false,
+            #[cfg(feature = "extend")]
+            last: 0,
+            #[cfg(feature = "extend")]
+            on_overflow: None,
+            #[cfg(feature = "extend")]
+            missed_reloads: 0,
+            #[cfg(feature = "extend")]
+            long_target: None,
+            #[cfg(all(feature = "extend", feature = "diagnostics"))]
+            max_now_gap: 0,
+            #[cfg(feature = "diagnostics")]
+            set_compare_cycles: 0,
+            #[cfg(debug_assertions)]
+            time_scale: (1, 1),
+        }
+    }
+
+    /// Check that `sysclk` still matches `TIMER_HZ`.
+    ///
+    /// The check in `new()` only covers the clock configuration at that time.
+    /// Call this after the HAL reconfigured the clocks.
+    #[inline(always)]
+    pub fn revalidate(&self, sysclk: u32) -> Result<(), ClockMismatch> {
+        Self::check_clock(sysclk)
+    }
+
+    fn check_reference_clock(reference_hz: u32) -> Result<NonZeroU32, InitError> {
+        match NonZeroU32::new(reference_hz) {
+            Some(hz) if hz.get() <= TIMER_HZ && hz.get() as u64 * 128 >= TIMER_HZ as u64 => Ok(hz),
+            _ => Err(InitError::ReferenceClock),
+        }
+    }
+
+    fn check_clock(sysclk: u32) -> Result<(), ClockMismatch> {
+        if sysclk == TIMER_HZ {
+            Ok(())
+        } else {
+            Err(ClockMismatch {
+                expected: TIMER_HZ,
+                actual: sysclk,
+            })
+        }
+    }
+
+    /// Convert the tick count from `old_hz` to `new_hz` ticks after a clock change.
+    ///
+    /// `TIMER_HZ` can't change at runtime, so the application constructs a new
+    /// monotonic for `new_hz` with `DwtSystick::new_keep_count()` (and, with the
+    /// `extend` feature, carries the tick count over with `restore()`). The count
+    /// is still in ticks of `old_hz` and this scales it so `now()` continues the
+    /// old timeline. Call it right after the clock switch.
+    ///
+    /// The source has to support `set()`. A `SharedCycleSource` ignores it,
+    /// so its count can't be rescaled and this must not be used with it.
+    ///
+    /// Panics if `new_hz` is not `TIMER_HZ` or `old_hz` is zero. Without the
+    /// `extend` feature the scaled count wraps at the cycle counter width.
+    pub fn rescale_after_clock_change(&mut self, old_hz: u32, new_hz: u32) {
+        assert!(new_hz == TIMER_HZ && old_hz > 0);
+
+        let ticks = muldiv(self.peek_ticks(), new_hz, old_hz);
+        self.source.set(ticks as u32);
+
+        #[cfg(feature = "extend")]
+        {
+            self.last = ticks;
+        }
+    }
+
+    /// Verify that the cycle counter advances and the SysTick fires on time.
+    ///
+    /// This blocks for a few thousand cycles and can be called in `#[init]`,
+    /// before `reset()`: it starts both counters but does not touch the epoch.
+    /// It temporarily uses the SysTick. If the SysTick exception is enabled
+    /// and not masked, it fires during the test.
+    pub fn self_test(&mut self) -> Result<(), SelfTestError> {
+        self.source.start();
+        self.systick.enable_counter();
+
+        if !self.verify_cycle_counter() {
+            return Err(SelfTestError::CounterStalled);
+        }
+
+        self.systick.set_reload(SELF_TEST_RELOAD);
+        self.systick.clear_current();
+        // Clear COUNTFLAG
+        self.systick.has_wrapped();
+
+        // The SysTick may run from a slower reference clock.
+        let expected = self.to_cycles(SELF_TEST_RELOAD as u64) as u32;
+        let start = self.source.read();
+        let result = loop {
+            let elapsed = self.source.read().wrapping_sub(start);
+            if self.systick.has_wrapped() {
+                if elapsed < expected {
+                    break Err(SelfTestError::CompareEarly);
+                }
+                break Ok(());
+            }
+            if elapsed > 4 * expected {
+                break Err(SelfTestError::CompareTimeout);
+            }
+            core::hint::spin_loop();
+        };
+
+        self.systick.set_reload(0xff_ffff);
+        self.systick.clear_current();
+
+        result
+    }
+
+    /// Measure the core clock against the SysTick reference clock.
+    ///
+    /// The SysTick is switched to its external reference clock and reloaded
+    /// with the `TENMS` calibration value, nominally 10 ms. The cycle counter
+    /// is sampled at two consecutive SysTick wraps and the cycles of that
+    /// window are compared with `TIMER_HZ / 100`. Deviations above the
+    /// tolerance (see `set_clock_tolerance_ppm()`) report `Drifted`, e.g. after
+    /// a PLL unlock.
+    ///
+    /// This busy-waits for up to about 20 ms with the SysTick exception
+    /// disabled. Afterwards the SysTick is restored and fires right away so a
+    /// pending compare is re-armed. The calibration value is only as accurate
+    /// as the reference clock; parts where `SKEW` is set have an inexact value.
+    pub fn clock_health(&mut self) -> ClockHealth {
+        let tenms = SYST::get_ticks_per_10ms();
+        if !SYST::has_reference_clock() || tenms == 0 {
+            return ClockHealth::NoReference;
+        }
+
+        let interrupt = self.systick.is_interrupt_enabled();
+        let source = self.systick.get_clock_source();
+        self.systick.disable_interrupt();
+        self.systick.set_clock_source(SystClkSource::External);
+        self.systick.set_reload(tenms - 1);
+        self.systick.clear_current();
+        // Clear COUNTFLAG
+        self.systick.has_wrapped();
+
+        // Give up after four windows' worth of cycles per wait.
+        let timeout = TIMER_HZ / 25;
+        let mut wait_wrap = || {
+            let start = self.source.read();
+            while !self.systick.has_wrapped() {
+                if self.source.read().wrapping_sub(start) > timeout {
+                    return None;
+                }
+                core::hint::spin_loop();
+            }
+            Some(self.source.read())
+        };
+        let window = wait_wrap().and_then(|start| Some(wait_wrap()?.wrapping_sub(start)));
+
+        self.systick.set_clock_source(source);
+        self.systick.set_reload(self.min_reload);
+        self.systick.clear_current();
+        if interrupt {
+            self.systick.enable_interrupt();
+        }
+
+        let Some(window) = window else {
+            return ClockHealth::NoReference;
+        };
+        let measured_hz = window.saturating_mul(100);
+        let deviation = muldiv(measured_hz.abs_diff(TIMER_HZ) as u64, 1_000_000, TIMER_HZ);
+        if deviation > self.clock_tolerance_ppm as u64 {
+            ClockHealth::Drifted { measured_hz }
+        } else {
+            ClockHealth::Ok { measured_hz }
+        }
+    }
+
+    /// Set the tolerance of `clock_health()` in parts per million (default
+    /// 10000, 1%).
+    ///
+    /// This has to cover the accuracy of the SysTick reference clock and its
+    /// calibration value as well as the sampling error of a few cycles.
+    #[inline(always)]
+    pub fn set_clock_tolerance_ppm(&mut self, ppm: u32) {
+        self.clock_tolerance_ppm = ppm;
+    }
+
+    /// Check that the cycle counter is actually advancing.
+    ///
+    /// `DWT::has_cycle_counter()` only checks that the counter is present. On
+    /// some parts it can be present but not counting. Call this after `reset()`
+    /// to catch a dead counter before the monotonic returns a constant time.
+    pub fn verify_cycle_counter(&self) -> bool {
+        let start = self.source.read();
+        for _ in 0..16 {
+            cortex_m::asm::nop();
+        }
+        self.source.read() != start
+    }
+
+    /// Busy-wait for `d`.
+    ///
+    /// This polls the cycle counter and needs neither the SysTick nor
+    /// interrupts. Durations longer than the counter range are supported.
+    #[inline(always)]
+    pub fn delay(&self, d: <Self as Monotonic>::Duration) {
+        self.delay_ticks(d.ticks() as _);
+    }
+
+    /// Busy-wait for `micros` microseconds, converted to ticks with `rounding`.
+    ///
+    /// Use `Rounding::Ceil` for delays that must last at least `micros`.
+    #[inline(always)]
+    pub fn delay_micros(&self, micros: u64, rounding: Rounding) {
+        self.delay_ticks(Self::micros_to_ticks_rounded(micros, rounding));
+    }
+
+    /// Busy-wait for at least `d`, a `core::time::Duration`.
+    ///
+    /// The tick count is rounded up and saturates at `u64::MAX` ticks, far
+    /// beyond any practical delay. Durations longer than the cycle counter
+    /// range are supported like in `delay()`.
+    #[inline(always)]
+    pub fn delay_core(&self, d: core::time::Duration) {
+        self.delay_ticks(Self::core_duration_to_ticks(d));
+    }
+
+    /// Convert a `core::time::Duration` to ticks, rounding up.
+    const fn core_duration_to_ticks(d: core::time::Duration) -> u64 {
+        let nanos = mul_div_rounded(
+            d.subsec_nanos() as u64,
+            TIMER_HZ,
+            1_000_000_000,
+            Rounding::Ceil,
+        );
+        d.as_secs()
+            .saturating_mul(TIMER_HZ as u64)
+            .saturating_add(nanos)
+    }
+
+    /// Borrow the monotonic as an `embedded_hal::timer::CountDown`.
+    ///
+    /// This lets drivers written against the `embedded-hal` 0.2 timer traits
+    /// use the monotonic. The count is already expired until `start()` is
+    /// called.
+    #[cfg(feature = "embedded-hal")]
+    pub fn count_down(&mut self) -> CountDown<'_, S, TIMER_HZ> {
+        CountDown::new(self)
+    }
+
+    /// Busy-wait for `ticks` cycles.
+    fn delay_ticks(&self, ticks: u64) {
+        wait_ticks(ticks, || self.source.read());
+    }
+
+    /// Enable the SysTick exception.
+    ///
+    /// With the `extend` feature, SysTick reloads that occurred while the
+    /// exception was disabled are counted (see `missed_reloads()`), the
+    /// overflow tracking is caught up with a `now()` and the SysTick exception
+    /// is pended so the missed compare is handled.
+    ///
+    /// The reloads are detected with `COUNTFLAG`, which is lossy: any write of
+    /// the SysTick current value, as in every `set_compare()` outside
+    /// free-running mode, and any read of the SysTick control register clear
+    /// it. A reload followed by either while disabled goes unnoticed.
+    #[inline(always)]
+    pub fn enable_interrupt(&mut self) {
+        #[cfg(feature = "extend")]
+        if self.count_flag_set() {
+            self.missed_reloads = self.missed_reloads.saturating_add(1);
+            self.now();
+            SCB::set_pendst();
+        }
+
+        self.systick.enable_interrupt();
+    }
+
+    /// Disable the SysTick exception.
+    ///
+    /// With the `extend` feature, the exception must not be disabled longer than
+    /// half the cycle counter overflow period or overflows will be missed.
+    #[inline(always)]
+    pub fn disable_interrupt(&mut self) {
+        self.systick.disable_interrupt();
+
+        // Clear COUNTFLAG so `enable_interrupt()` only sees reloads while
+        // the exception was disabled.
+        #[cfg(feature = "extend")]
+        self.count_flag_set();
+    }
+
+    /// Whether the SysTick reached zero since the flag was last read.
+    ///
+    /// This reads the SysTick `COUNTFLAG`, which clears it. Any read of the
+    /// SysTick control register also clears it.
+    #[inline(always)]
+    pub fn count_flag_set(&mut self) -> bool {
+        self.systick.has_wrapped()
+    }
+
+    /// The number of `enable_interrupt()` calls that found a SysTick reload
+    /// had occurred while the exception was disabled.
+    ///
+    /// Any such reload was not seen by the interrupt handler. Several reloads
+    /// while disabled count once.
+    #[cfg(feature = "extend")]
+    #[inline(always)]
+    pub fn missed_reloads(&self) -> u32 {
+        self.missed_reloads
+    }
+
+    /// Arm the SysTick in `reset()` to fire `d` after it and then periodically
+    /// every `d`.
+    ///
+    /// This starts a periodic SysTick interrupt without a first `set_compare()`,
+    /// e.g. for standalone use without RTIC: `reset()` also enables the SysTick
+    /// interrupt and records the compare for `armed_deadline()`. `d` is clamped
+    /// like in `set_compare()`. With RTIC, the first scheduled task overwrites this
+    /// compare, and with the `extend` feature `clear_compare_flag()` resets
+    /// the reload after the first interrupt.
+    #[inline(always)]
+    pub fn initial_compare(mut self, d: <Self as Monotonic>::Duration) -> Self {
+        let ticks = self.to_systick_ticks(d.ticks() as _);
+        self.initial_reload = Some(ticks.clamp(self.min_reload as _, self.max_reload as _) as _);
+        self
+    }
+
+    /// The SysTick reload for a periodic interrupt at `hz`.
+    ///
+    /// The period is the SysTick clock divided by `hz`, rounded down, and has
+    /// to fit the 24-bit SysTick.
+    pub fn reload_for_hz(&self, hz: u32) -> Result<u32, ReloadError> {
+        match self.systick_hz().checked_div(hz) {
+            None | Some(0x100_0001..) => Err(ReloadError::TooSlow),
+            Some(0..=1) => Err(ReloadError::TooFast),
+            Some(period) => Ok(period - 1),
+        }
+    }
+
+    /// Start a periodic SysTick interrupt at `hz`, see `reload_for_hz()`.
+    ///
+    /// This is for standalone use without RTIC. `set_compare()` and, with the
+    /// `extend` feature, `clear_compare_flag()` change the reload.
+    pub fn start_periodic(&mut self, hz: u32) -> Result<(), ReloadError> {
+        let reload = self.reload_for_hz(hz)?;
+        self.systick.set_reload(reload);
+        self.systick.clear_current();
+        self.systick.enable_counter();
+        self.systick.enable_interrupt();
+        Ok(())
+    }
+
+    /// Stop the SysTick counter to save power, e.g. in a super-loop without
+    /// pending deadlines.
+    ///
+    /// The cycle counter keeps running, so the time stays continuous. No
+    /// compare fires until `resume_from_idle()`. With the `extend` feature the
+    /// SysTick interrupt no longer tracks overflows, so `now()` still has to
+    /// be called at least once per cycle counter overflow period.
+    #[inline(always)]
+    pub fn idle(&mut self) {
+        self.systick.disable_counter();
+    }
+
+    /// Restart the SysTick counter after `idle()`.
+    ///
+    /// The SysTick fires after the minimum reload so the compare can be
+    /// re-armed from the interrupt.
+    #[inline(always)]
+    pub fn resume_from_idle(&mut self) {
+        self.systick.set_reload(self.min_reload);
+        self.systick.clear_current();
+        self.systick.enable_counter();
+    }
+
+    /// Set the minimum SysTick reload used by `set_compare()` (default 1).
+    ///
+    /// Deadlines closer than `min` ticks, including those already in the past,
+    /// fire after `min` ticks. A larger minimum bounds the interrupt rate when
+    /// many deadlines pile up in the past, at the cost of up to `min` ticks of
+    /// extra latency. The value is clamped to `1` and the maximum reload (see
+    /// `set_max_reload()`).
+    #[inline(always)]
+    pub fn set_min_reload(&mut self, min: u32) {
+        self.min_reload = min.clamp(1, self.max_reload);
+    }
+
+    /// Set the maximum SysTick reload (default `0xff_ffff`).
+    ///
+    /// Both `set_compare()` and, with the `extend` feature, the overflow
+    /// tracking interrupt fire at least every `max` ticks. A smaller maximum
+    /// costs more interrupts and power but leaves a larger margin against
+    /// missed cycle counter overflows, e.g. when interrupts are masked for a
+    /// while. The value is clamped to the minimum reload and `0xff_ffff`.
+    #[inline(always)]
+    pub fn set_max_reload(&mut self, max: u32) {
+        self.max_reload = max.clamp(self.min_reload, 0xff_ffff);
+    }
+
+    /// Pend PendSV from `on_interrupt()`.
+    ///
+    /// This allows keeping the SysTick handler short and deferring application
+    /// work to the PendSV handler. The overflow tracking of the `extend` feature
+    /// is still done in the SysTick handler.
+    ///
+    /// PendSV must be configured at a lower priority than SysTick, otherwise
+    /// it preempts the SysTick handler and nothing is deferred.
+    #[inline(always)]
+    pub fn set_pendsv_deferral(&mut self, enable: bool) {
+        self.pendsv = enable;
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(not(feature = "extend"))] {
+            /// Current tick count without updating any state.
+            #[inline(always)]
+            fn peek_ticks(&self) -> u64 {
+                self.source.read() as u64
+            }
+        } else {
+            /// Current tick count without storing the result.
+            #[inline(always)]
+            fn peek_ticks(&self) -> u64 {
+                extend(self.last, self.source.read())
+            }
+        }
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(not(feature = "extend"))] {
+            /// Current tick count as `u64`.
+            ///
+            /// This is the same integer type with or without the `extend` feature
+            /// so code using it compiles in both configurations. Without `extend`
+            /// the value is the 32-bit count widened and wraps at `u32::MAX`.
+            #[inline(always)]
+            pub fn tick_count(&mut self) -> u64 {
+                self.now().ticks() as u64
+            }
+        } else {
+            /// Current tick count as `u64`.
+            ///
+            /// This is the same integer type with or without the `extend` feature
+            /// so code using it compiles in both configurations. Without `extend`
+            /// the value is the 32-bit count widened and wraps at `u32::MAX`.
+            #[inline(always)]
+            pub fn tick_count(&mut self) -> u64 {
+                self.now().ticks()
+            }
+        }
+    }
+
+    /// Keep the SysTick free-running when a compare is set.
+    ///
+    /// By default `set_compare()` clears the SysTick counter so the new reload
+    /// is loaded on the next cycle and the deadline is hit exactly. In
+    /// free-running mode the reload is only written if it changed and the
+    /// counter isn't cleared, preserving the phase of a steady periodic tick.
+    /// A new reload then only takes effect after the SysTick next reaches
+    /// zero, so the interrupt fires at the old phase, before the deadline if
+    /// the counter wraps first. A deadline passed after that early interrupt is
+    /// only hit if `set_compare()` is called again from it, as RTIC does.
+    /// Otherwise the following full period overshoots it. A deadline before
+    /// the next wrap clears the counter like the default mode instead of
+    /// firing late.
+    #[inline(always)]
+    pub fn set_free_running(&mut self, enable: bool) {
+        self.free_running = enable;
+    }
+
+    /// Scale the time reported by `now()` by `numerator / denominator`, for
+    /// soak testing only.
+    ///
+    /// E.g. `set_time_scale(3_600, 1)` makes one real second appear as one
+    /// hour, exercising long uptimes and counter wraps quickly. The hardware
+    /// counters are not touched. All instants and durations of the monotonic
+    /// are scaled, and compares (`set_compare()`, `set_compare_relative_to()`,
+    /// `arm_dwt_comparator()`) are converted back to real ticks, so scheduling
+    /// keeps working. The scaled time is monotonic as long as the scale is set
+    /// once, before the monotonic is used. Without the `extend` feature the
+    /// scaled count jumps at each real cycle counter wrap unless `denominator`
+    /// is 1.
+    ///
+    /// Not scaled are delays, the raw counter value of `now_raw()` and the
+    /// time of `register_global()`, `now_shared()` and `TimeReader`.
+    ///
+    /// Only available with debug assertions. Panics if either value is zero.
+    #[cfg(debug_assertions)]
+    pub fn set_time_scale(&mut self, numerator: u32, denominator: u32) {
+        assert!(numerator > 0 && denominator > 0, "zero time scale");
+        self.time_scale = (numerator, denominator);
+    }
+
+    /// Switch to polling and locate the epoch like `reset()`, without the
+    /// SysTick.
+    #[inline(always)]
+    fn start_polling(&mut self) {
+        self.polling = true;
+        self.source.reset();
+        self.started = true;
+    }
+
+    /// Whether `reset()` has been called, locating the epoch. A monotonic from
+    /// `new_polling()` is started right away.
+    ///
+    /// Before that, e.g. in RTIC's `#[init]`, `now()` reads the counter
+    /// relative to whatever it held at construction and the timestamps are
+    /// not comparable with later ones.
+    #[inline(always)]
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Like `now()` but `None` before `reset()` has been called.
+    #[inline(always)]
+    pub fn try_now(&mut self) -> Option<<Self as Monotonic>::Instant> {
+        if self.started {
+            Some(self.now())
+        } else {
+            None
+        }
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(not(feature = "extend"))] {
+            /// Signed difference `b - a` in ticks.
+            ///
+            /// The result is only valid if the instants are less than half the
+            /// counter range (`2^31` ticks) apart.
+            #[inline(always)]
+            pub fn cycles_between(
+                a: <Self as Monotonic>::Instant,
+                b: <Self as Monotonic>::Instant,
+            ) -> i64 {
+                b.ticks().wrapping_sub(a.ticks()) as i32 as i64
+            }
+        } else {
+            /// Signed difference `b - a` in ticks.
+            #[inline(always)]
+            pub fn cycles_between(
+                a: <Self as Monotonic>::Instant,
+                b: <Self as Monotonic>::Instant,
+            ) -> i64 {
+                b.ticks().wrapping_sub(a.ticks()) as i64
+            }
+        }
+    }
+
+    /// Current tick count as `u64`, the same as `tick_count()`.
+    #[inline(always)]
+    pub fn now_ticks(&mut self) -> u64 {
+        self.tick_count()
+    }
+
+    /// Store the current tick count in `out`, e.g. directly into a log record.
+    ///
+    /// This performs the same overflow tracking as `now()`.
+    #[inline(always)]
+    pub fn write_now(&mut self, out: &mut u64) {
+        *out = self.tick_count();
+    }
+
+    /// Arm the compare for an absolute tick count, like `set_compare()`.
+    ///
+    /// Without the `extend` feature `target_ticks` is truncated to the 32-bit
+    /// counter width.
+    #[inline(always)]
+    pub fn set_compare_ticks(&mut self, target_ticks: u64) {
+        self.set_compare(<Self as Monotonic>::Instant::from_ticks(target_ticks as _));
+    }
+
+    /// Arm the compare for `base + offset` without a full `now()`.
+    ///
+    /// Only the raw cycle counter is read, skipping the overflow tracking of the
+    /// `extend` feature, which removes jitter when `base` has just been captured,
+    /// e.g. in a control loop. `base` must not be in the future and not more than
+    /// one cycle counter period (`2^32` ticks) old, otherwise the reload is wrong.
+    /// With the `extend` feature an offset beyond the SysTick window is
+    /// approached in steps like in `set_compare()`.
+    #[inline(always)]
+    pub fn set_compare_relative_to(
+        &mut self,
+        base: <Self as Monotonic>::Instant,
+        offset: <Self as Monotonic>::Duration,
+    ) {
+        let reload = self.ticks_after(base, offset);
+        self.arm(reload);
+
+        // Continued from `clear_compare_flag()` like in `set_compare()`.
+        #[cfg(feature = "extend")]
+        if self.to_systick_ticks(reload) > self.max_reload as u64 {
+            self.long_target = Some(base.ticks().saturating_add(offset.ticks()));
+        }
+    }
+
+    /// Real cycle counter ticks from now until `base + offset`, from the raw
+    /// counter only.
+    #[inline(always)]
+    fn ticks_after(
+        &self,
+        base: <Self as Monotonic>::Instant,
+        offset: <Self as Monotonic>::Duration,
+    ) -> u64 {
+        let base: u32 = self.unscaled(base.ticks() as _) as _;
+        let offset = self.unscaled(offset.ticks() as _);
+        let elapsed = self.source.read().wrapping_sub(base);
+        offset.saturating_sub(elapsed as u64)
+    }
+
+    /// Whether `deadline` has been reached.
+    ///
+    /// This is the polling counterpart to `set_compare()`, see `new_polling()`.
+    #[inline(always)]
+    pub fn poll_deadline(&mut self, deadline: <Self as Monotonic>::Instant) -> bool {
+        self.now() >= deadline
+    }
+
+    /// Arm the compare for the earliest of `deadlines` that is still ahead.
+    ///
+    /// `now()` is read once and deadlines at or before it are skipped. Returns
+    /// the armed deadline, or `None` without arming if none is in the future.
+    pub fn arm_earliest(
+        &mut self,
+        deadlines: &[<Self as Monotonic>::Instant],
+    ) -> Option<<Self as Monotonic>::Instant> {
+        let now = self.now();
+        let earliest = deadlines
+            .iter()
+            .copied()
+            .filter(|&deadline| deadline > now)
+            .min_by_key(|&deadline| deadline - now)?;
+
+        self.set_compare(earliest);
+        Some(earliest)
+    }
+
+    /// Read the time together with the raw cycle counter value it was computed
+    /// from.
+    ///
+    /// This allows checking the overflow tracking of the `extend` feature
+    /// against the hardware register.
+    #[inline(always)]
+    pub fn now_raw(&mut self) -> (<Self as Monotonic>::Instant, u32) {
+        let raw = self.source.read();
+
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "extend")] {
+                let now = self.track(raw);
+            } else {
+                let now = <Self as Monotonic>::Instant::from_ticks(raw);
+            }
+        }
+
+        (self.scaled(now), raw)
+    }
+
+    /// Record the current time before a section that masks interrupts.
+    ///
+    /// This also updates the overflow tracking of the `extend` feature, so the
+    /// full safety window is available to the section. Measure it with
+    /// `since_checkpoint()`.
+    #[inline(always)]
+    pub fn checkpoint(&mut self) {
+        self.checkpoint = self.now_raw().1;
+    }
+
+    /// The time elapsed since the last `checkpoint()`.
+    ///
+    /// This only uses the difference of the cycle counter readings, so it is
+    /// correct for gaps up to one cycle counter period whether or not the
+    /// overflow tracking caught up. Gaps longer than half the period are close
+    /// to being indistinguishable from short ones and trigger a debug assertion.
+    #[inline(always)]
+    pub fn since_checkpoint(&mut self) -> <Self as Monotonic>::Duration {
+        let elapsed = self.now_raw().1.wrapping_sub(self.checkpoint);
+        debug_assert!(
+            elapsed <= i32::MAX as u32,
+            "since_checkpoint() gap exceeds half the cycle counter period"
+        );
+        <Self as Monotonic>::Duration::from_ticks(self.scale(elapsed as _) as _)
+    }
+
+    /// Whether a deadline beyond the SysTick window is still being approached.
+    ///
+    /// With the `extend` feature, `set_compare()` arms deadlines further away
+    /// than the maximum reload in steps: each SysTick interrupt re-arms towards
+    /// the deadline in `clear_compare_flag()` until it is reached. Without RTIC
+    /// this tells the interrupt handler that the interrupt was an intermediate
+    /// step: after `clear_compare_flag()` it stays true through the final step
+    /// and only turns false in the interrupt at the deadline. Without `extend`
+    /// the compare is always a single step.
+    #[inline(always)]
+    pub fn compare_pending(&self) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "extend")] {
+                self.long_target.is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    /// The instant the SysTick is armed to fire at.
+    ///
+    /// This is the deadline of the last `set_compare()` after clamping, e.g. a
+    /// long deadline clamped to the SysTick range, or the overflow tracking
+    /// reload of the `extend` feature. It is reconstructed from the time of
+    /// arming plus the reload and is accurate to a few cycles. Without the
+    /// `extend` feature it wraps with the cycle counter like all instants.
+    #[inline(always)]
+    pub fn armed_deadline(&self) -> <Self as Monotonic>::Instant {
+        self.scaled(<Self as Monotonic>::Instant::from_ticks(self.armed as _))
+    }
+
+    /// Cycle counter ticks remaining until the low word wraps.
+    ///
+    /// This is mainly meaningful with the `extend` feature, where `now()` must
+    /// be called at least once per overflow window to track the wrap.
+    #[inline(always)]
+    pub fn ticks_until_overflow(&mut self) -> u32 {
+        u32::MAX - self.tick_count() as u32
+    }
+
+    /// The instant at which the low word of the cycle counter next wraps.
+    ///
+    /// This is the current tick count rounded up to the next `2^32` boundary.
+    /// Idle code that keeps no other deadline can schedule a wake-up slightly
+    /// before this instant, anything within the overflow window after the
+    /// previous `now()`, which guarantees the wrap is detected.
+    #[cfg(feature = "extend")]
+    #[inline(always)]
+    pub fn next_overflow_instant(&mut self) -> <Self as Monotonic>::Instant {
+        let high = self.now().ticks() >> 32;
+        <Self as Monotonic>::Instant::from_ticks((high + 1).saturating_mul(1 << 32))
+    }
+
+    /// Read the current tick count as its high and low 32-bit words.
+    ///
+    /// This uses the same overflow tracking as `now()`. Without the `extend`
+    /// feature the high word is always zero.
+    #[inline(always)]
+    pub fn now_split(&mut self) -> (u32, u32) {
+        let ticks = self.tick_count();
+        ((ticks >> 32) as u32, ticks as u32)
+    }
+
+    /// Sample the monotonic together with a value read by `read`.
+    ///
+    /// The time is read before and after `read` and the midpoint is returned,
+    /// which minimizes the skew between instant and value. This costs a second
+    /// `now()` over a plain read.
+    #[inline(always)]
+    pub fn now_with<T>(&mut self, read: impl FnOnce() -> T) -> (<Self as Monotonic>::Instant, T) {
+        let start = self.now().ticks();
+        let value = read();
+        let end = self.now().ticks();
+        let mid = start.wrapping_add(end.wrapping_sub(start) / 2);
+        (<Self as Monotonic>::Instant::from_ticks(mid), value)
+    }
+
+    /// The raw tick count of `instant`, e.g. for use as a map key.
+    ///
+    /// This is `u64` with and without the `extend` feature.
+    #[inline(always)]
+    pub fn instant_key(instant: <Self as Monotonic>::Instant) -> u64 {
+        instant.ticks() as _
+    }
+
+    /// Add `dur` to `instant`, returning `None` if the tick count overflows.
+    ///
+    /// Unlike `instant + dur` this does not wrap silently. Without the `extend`
+    /// feature the tick count is `u32` and overflows with the cycle counter.
+    #[inline(always)]
+    pub fn checked_add(
+        instant: <Self as Monotonic>::Instant,
+        dur: <Self as Monotonic>::Duration,
+    ) -> Option<<Self as Monotonic>::Instant> {
+        instant
+            .ticks()
+            .checked_add(dur.ticks())
+            .map(<Self as Monotonic>::Instant::from_ticks)
+    }
+
+    /// Subtract `dur` from `instant`, saturating at the epoch `zero()`.
+    ///
+    /// Unlike `instant - dur` this does not wrap to a far future instant, e.g.
+    /// when subtracting a lead time shortly after startup.
+    #[inline(always)]
+    pub fn saturating_sub(
+        instant: <Self as Monotonic>::Instant,
+        dur: <Self as Monotonic>::Duration,
+    ) -> <Self as Monotonic>::Instant {
+        <Self as Monotonic>::Instant::from_ticks(instant.ticks().saturating_sub(dur.ticks()))
+    }
+
+    /// The tick count divided by `2^shift`, truncated to 16 bits.
+    ///
+    /// This is intended for compact log formats where only short intervals
+    /// between records matter. The value wraps every `2^(16 + shift)` ticks,
+    /// which is the longest interval it can represent.
+    #[inline(always)]
+    pub fn now_u16(&mut self, shift: u32) -> u16 {
+        self.tick_count().checked_shr(shift).unwrap_or(0) as u16
+    }
+
+    /// The middle 32 bits of the tick count, a slowly wrapping timestamp.
+    ///
+    /// This is the tick count divided by `2^16`, so it advances at
+    /// `TIMER_HZ / 65536` and wraps every `2^48` ticks (about 19 days at
+    /// 168 MHz). Without the `extend` feature the tick count itself wraps every
+    /// `2^32` ticks, so only 16 bits are used.
+    #[inline(always)]
+    pub fn seq32(&mut self) -> u32 {
+        (self.tick_count() >> 16) as u32
+    }
+
+    /// Call `f` whenever `now()` detects a cycle counter overflow.
+    ///
+    /// `f` runs in the context that called `now()`, typically the SysTick
+    /// interrupt, and should be short.
+    #[cfg(feature = "extend")]
+    #[inline(always)]
+    pub fn set_overflow_callback(&mut self, f: fn()) {
+        self.on_overflow = Some(f);
+    }
+
+    /// The largest gap in ticks observed between consecutive `now()` calls.
+    ///
+    /// A gap of `u32::MAX` or more can't be told apart from a shorter one and
+    /// loses an overflow. A value approaching that means `now()` is not called
+    /// often enough.
+    #[cfg(all(feature = "extend", feature = "diagnostics"))]
+    #[inline(always)]
+    pub fn max_now_gap(&self) -> u32 {
+        self.max_now_gap
+    }
+
+    /// The running average of the cycles spent in `set_compare()`.
+    ///
+    /// This is measured with the cycle counter itself and averaged over about
+    /// the last 16 calls. It includes the measurement overhead.
+    #[cfg(feature = "diagnostics")]
+    #[inline(always)]
+    pub fn avg_set_compare_cycles(&self) -> u32 {
+        self.set_compare_cycles / 16
+    }
+
+    /// Combine a caller-tracked high word with the current cycle counter.
+    ///
+    /// This neither reads nor updates the overflow tracking of the monotonic,
+    /// so it can be used through a shared reference when overflows are tracked
+    /// elsewhere. The caller is responsible for keeping `high` current.
+    #[cfg(feature = "extend")]
+    #[inline(always)]
+    pub fn now_with_high(&self, high: u32) -> <Self as Monotonic>::Instant {
+        <Self as Monotonic>::Instant::from_ticks(((high as u64) << 32) | self.source.read() as u64)
+    }
+
+    /// Like `set_compare()` but reject deadlines more than one cycle counter
+    /// overflow period (`2^32` ticks) in the future.
+    #[cfg(feature = "extend")]
+    pub fn try_set_compare(
+        &mut self,
+        val: <Self as Monotonic>::Instant,
+    ) -> Result<(), DeadlineTooFar> {
+        if let Some(duration) = val.checked_duration_since(self.now()) {
+            if duration.ticks() > u32::MAX as u64 {
+                return Err(DeadlineTooFar);
+            }
+        }
+
+        self.set_compare(val);
+        Ok(())
+    }
+
+    /// Arm the compare `dur` from now, rejecting durations the tick count can't
+    /// represent.
+    ///
+    /// Without the `extend` feature, instants more than half the cycle counter
+    /// period (`2^31` ticks) apart compare the wrong way round, so such a
+    /// deadline would silently be treated as in the past. With `extend` the
+    /// limit is one cycle counter period, like `try_set_compare()`. Returns
+    /// the armed deadline.
+    pub fn try_set_compare_after(
+        &mut self,
+        dur: <Self as Monotonic>::Duration,
+    ) -> Result<<Self as Monotonic>::Instant, DeadlineTooFar> {
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "extend")] {
+                let horizon = u32::MAX as u64;
+            } else {
+                let horizon = i32::MAX as u32;
+            }
+        }
+        if dur.ticks() > horizon {
+            return Err(DeadlineTooFar);
+        }
+
+        let deadline = self.now() + dur;
+        self.set_compare(deadline);
+        Ok(deadline)
+    }
+
+    /// The time elapsed since the epoch, the tick count of `now()` as a duration.
+    ///
+    /// The epoch is the last `reset()` (or `new_polling()`), except with
+    /// `new_keep_count()`. Without the `extend` feature this restarts from zero
+    /// when the cycle counter wraps. It is not `now() - zero()`, which panics
+    /// once the wrap-aware comparison puts `zero()` after `now()`.
+    #[inline(always)]
+    pub fn since_epoch(&mut self) -> <Self as Monotonic>::Duration {
+        <Self as Monotonic>::Duration::from_ticks(self.now().ticks())
+    }
+
+    /// Whether more than `d` has elapsed since the epoch, see `since_epoch()`.
+    #[inline(always)]
+    pub fn elapsed_exceeds(&mut self, d: <Self as Monotonic>::Duration) -> bool {
+        self.since_epoch() > d
+    }
+
+    /// Correct the time conversions for a clock that is `ppm` parts per million
+    /// fast (positive) or slow (negative) relative to `TIMER_HZ`.
+    ///
+    /// This applies to `subsecond_ticks()`, `seconds()`, `now_nanos()`,
+    /// `uptime()` and `system_ticks_ms()`. The ticks, instants and scheduling
+    /// are unchanged. The error can be measured against a reference, e.g. from
+    /// the `measured_hz` of `clock_health()` as
+    /// `(measured_hz - TIMER_HZ) * 1_000_000 / TIMER_HZ`.
+    #[inline(always)]
+    pub fn set_trim_ppm(&mut self, ppm: i32) {
+        self.trim_ppm = ppm;
+    }
+
+    /// The actual tick frequency after the trim correction.
+    #[inline(always)]
+    fn trimmed_hz(&self) -> u32 {
+        if self.trim_ppm == 0 {
+            return TIMER_HZ;
+        }
+
+        let scale = (1_000_000 + self.trim_ppm as i64).max(0) as u32;
+        muldiv(TIMER_HZ as u64, scale, 1_000_000).clamp(1, u32::MAX as u64) as u32
+    }
+
+    /// Ticks elapsed within the current second since the epoch.
+    #[inline(always)]
+    pub fn subsecond_ticks(&mut self) -> u32 {
+        (self.tick_count() % self.trimmed_hz() as u64) as u32
+    }
+
+    /// Whole seconds elapsed since the epoch located by `reset()`.
+    ///
+    /// This is not wall clock time. Without the `extend` feature it wraps with
+    /// the cycle counter.
+    #[inline(always)]
+    pub fn seconds(&mut self) -> u64 {
+        self.tick_count() / self.trimmed_hz() as u64
+    }
+
+    /// The position within the current period of `period_ticks` ticks.
+    ///
+    /// This is the tick count modulo `period_ticks`. Without the `extend`
+    /// feature the count wraps every `2^32` ticks, so unless `period_ticks` is
+    /// a power of two the phase jumps at each cycle counter overflow.
+    ///
+    /// Panics if `period_ticks` is zero.
+    #[inline(always)]
+    pub fn phase_in_period(&mut self, period_ticks: u32) -> u32 {
+        (self.tick_count() % period_ticks as u64) as u32
+    }
+
+    /// The position within the current period as a fraction of `2^16`.
+    ///
+    /// See `phase_in_period()`. The result is rounded down, so a full period
+    /// maps to `0..=u16::MAX`.
+    #[inline(always)]
+    pub fn phase_fraction(&mut self, period_ticks: u32) -> u16 {
+        (((self.phase_in_period(period_ticks) as u64) << 16) / period_ticks as u64) as u16
+    }
+
+    /// Nanoseconds elapsed since the epoch located by `reset()`.
+    ///
+    /// The result saturates after about 584 years. Without the `extend`
+    /// feature it wraps with the cycle counter.
+    pub fn now_nanos(&mut self) -> u64 {
+        muldiv(self.tick_count(), 1_000_000_000, self.trimmed_hz())
+    }
+
+    /// The time elapsed since the epoch located by `reset()` as a
+    /// `core::time::Duration`.
+    ///
+    /// This is the same type with and without the `extend` feature. Without
+    /// it, the uptime is limited to one cycle counter period (`2^32` ticks)
+    /// and wraps back to zero.
+    #[inline(always)]
+    pub fn uptime(&mut self) -> core::time::Duration {
+        core::time::Duration::from_nanos(self.now_nanos())
+    }
+
+    /// Whole milliseconds elapsed since the epoch located by `reset()`.
+    ///
+    /// This is a 1 kHz "system tick" counter. It is rounded down, so it only
+    /// advances once a full millisecond has elapsed. With the `extend` feature
+    /// it does not overflow for the life of the device. Without it, it wraps
+    /// back to zero with the cycle counter, every `2^32 / TIMER_HZ` seconds.
+    #[inline(always)]
+    pub fn system_ticks_ms(&mut self) -> u64 {
+        muldiv(self.tick_count(), 1_000, self.trimmed_hz())
+    }
+
+    /// Time elapsed since the epoch located by `reset()` as `(hours, minutes,
+    /// seconds, millis)`, for display.
+    ///
+    /// This is derived from `system_ticks_ms()` in a single read, so the fields
+    /// are consistent with each other. It is epoch-relative, not wall clock
+    /// time, and the hours don't roll over into days.
+    #[inline(always)]
+    pub fn hms(&mut self) -> (u32, u8, u8, u16) {
+        let ms = self.system_ticks_ms();
+        let secs = ms / 1_000;
+        (
+            (secs / 3_600).min(u32::MAX as u64) as u32,
+            (secs / 60 % 60) as u8,
+            (secs % 60) as u8,
+            (ms % 1_000) as u16,
+        )
+    }
+
+    /// Convert microseconds to ticks, rounding down.
+    #[inline(always)]
+    pub const fn micros_to_ticks(micros: u64) -> u64 {
+        Self::micros_to_ticks_rounded(micros, Rounding::Floor)
+    }
+
+    /// Convert ticks to microseconds, rounding down.
+    #[inline(always)]
+    pub const fn ticks_to_micros(ticks: u64) -> u64 {
+        Self::ticks_to_micros_rounded(ticks, Rounding::Floor)
+    }
+
+    /// Convert milliseconds to ticks, rounding down.
+    #[inline(always)]
+    pub const fn millis_to_ticks(millis: u64) -> u64 {
+        Self::millis_to_ticks_rounded(millis, Rounding::Floor)
+    }
+
+    /// Convert ticks to milliseconds, rounding down.
+    #[inline(always)]
+    pub const fn ticks_to_millis(ticks: u64) -> u64 {
+        Self::ticks_to_millis_rounded(ticks, Rounding::Floor)
+    }
+
+    /// Convert microseconds to ticks with the given rounding.
+    #[inline(always)]
+    pub const fn micros_to_ticks_rounded(micros: u64, rounding: Rounding) -> u64 {
+        mul_div_rounded(micros, TIMER_HZ, 1_000_000, rounding)
+    }
+
+    /// Convert ticks to microseconds with the given rounding.
+    #[inline(always)]
+    pub const fn ticks_to_micros_rounded(ticks: u64, rounding: Rounding) -> u64 {
+        mul_div_rounded(ticks, 1_000_000, TIMER_HZ, rounding)
+    }
+
+    /// Convert milliseconds to ticks with the given rounding.
+    #[inline(always)]
+    pub const fn millis_to_ticks_rounded(millis: u64, rounding: Rounding) -> u64 {
+        mul_div_rounded(millis, TIMER_HZ, 1_000, rounding)
+    }
+
+    /// Convert ticks to milliseconds with the given rounding.
+    #[inline(always)]
+    pub const fn ticks_to_millis_rounded(ticks: u64, rounding: Rounding) -> u64 {
+        mul_div_rounded(ticks, 1_000, TIMER_HZ, rounding)
+    }
+
+    /// The duration of one tick in picoseconds, rounded down.
+    #[inline(always)]
+    pub const fn resolution_ps(&self) -> u64 {
+        1_000_000_000_000 / TIMER_HZ as u64
+    }
+
+    /// The duration of one tick.
+    #[inline(always)]
+    pub const fn resolution(&self) -> <Self as Monotonic>::Duration {
+        <Self as Monotonic>::Duration::from_ticks(1)
+    }
+
+    /// Update the overflow tracking with the cycle counter reading `now`.
     #[cfg(feature = "extend")]
-    last: u64,
-}
+    #[inline(always)]
+    fn track(&mut self, now: u32) -> <Self as Monotonic>::Instant {
+        let last = self.last;
 
-impl<const TIMER_HZ: u32> DwtSystick<TIMER_HZ> {
-    /// Enable the DWT and provide a new `Monotonic` based on DWT and SysTick.
+        #[cfg(feature = "diagnostics")]
+        {
+            self.max_now_gap = self.max_now_gap.max(now.wrapping_sub(last as u32));
+        }
+
+        self.last = extend(last, now);
+
+        if self.global {
+            global::publish(self.last);
+        }
+
+        if self.last >> 32 != last >> 32 {
+            if let Some(f) = self.on_overflow {
+                f();
+            }
+        }
+
+        <Self as Monotonic>::Instant::from_ticks(self.last)
+    }
+
+    /// Convert cycle counter ticks to SysTick ticks, rounding up.
+    #[inline(always)]
+    fn to_systick_ticks(&self, ticks: u64) -> u64 {
+        match self.reference_hz {
+            None => ticks,
+            Some(hz) => mul_div_rounded(ticks, hz.get(), TIMER_HZ, Rounding::Ceil),
+        }
+    }
+
+    /// Convert SysTick ticks to cycle counter ticks, rounding down.
     ///
-    /// Note that the `sysclk` parameter should come from e.g. the HAL's clock generation function
-    /// so the speed calculated at runtime and the declared speed (generic parameter
-    /// `TIMER_HZ`) can be compared.
+    /// The non-zero reference clock saves the division by zero check.
+    #[inline(always)]
+    fn to_cycles(&self, ticks: u64) -> u64 {
+        match self.reference_hz {
+            None => ticks,
+            Some(hz) => muldiv(ticks, TIMER_HZ, hz.get()),
+        }
+    }
+
+    /// The SysTick clock frequency.
     #[inline(always)]
-    pub fn new(dcb: &mut DCB, mut dwt: DWT, mut systick: SYST, sysclk: u32) -> Self {
-        assert!(TIMER_HZ == sysclk);
+    fn systick_hz(&self) -> u32 {
+        self.reference_hz.map_or(TIMER_HZ, NonZeroU32::get)
+    }
 
-        dcb.enable_trace();
-        DWT::unlock();
-        assert!(DWT::has_cycle_counter());
+    /// Arm the SysTick to fire after `reload` ticks, clamped to the configured
+    /// minimum and the 24-bit SysTick range.
+    fn arm(&mut self, reload: u64) {
+        #[cfg(feature = "extend")]
+        {
+            self.long_target = None;
+        }
 
-        // Clear the cycle counter here so scheduling (`set_compare()`) before `reset()`
-        // works correctly.
-        dwt.set_cycle_count(0);
+        if self.polling {
+            return;
+        }
 
-        systick.set_clock_source(SystClkSource::Core);
+        // Catch the misconfiguration where the compare is armed but never
+        // wakes anything up. Reading the CSR clears COUNTFLAG.
+        debug_assert!(
+            self.systick.is_interrupt_enabled(),
+            "set_compare() called with the SysTick interrupt disabled"
+        );
 
-        // We do not start the counters here but in `reset()`.
+        let reload = self.systick_reload(reload);
 
-        DwtSystick {
-            dwt,
-            systick,
-            #[cfg(feature = "extend")]
-            last: 0,
+        #[cfg(feature = "trace-compares")]
+        {
+            // NOTE(unsafe): single register write, skipped if the FIFO is full
+            let stim = unsafe { &mut (*ITM::PTR).stim[TRACE_COMPARES_PORT] };
+            if stim.is_fifo_ready() {
+                stim.write_u32(reload);
+            }
+        }
+
+        self.load(reload);
+    }
+
+    /// Write `reload` to the SysTick and remember when it will fire.
+    #[inline(always)]
+    fn load(&mut self, reload: u32) {
+        if self.free_running {
+            // Keep the phase, the reload is loaded when the counter wraps,
+            // unless that is after the deadline.
+            let current = SYST::get_current();
+            if current <= reload {
+                if SYST::get_reload() != reload {
+                    self.systick.set_reload(reload);
+                }
+                self.record_armed(current);
+                return;
+            }
+        }
+
+        self.systick.set_reload(reload);
+        // Also clear the current counter. That doesn't cause a SysTick
+        // interrupt and loads the reload value on the next cycle.
+        self.systick.clear_current();
+        self.record_armed(reload);
+    }
+
+    /// The SysTick reload to fire after `ticks` cycle counter ticks.
+    #[inline(always)]
+    fn systick_reload(&self, ticks: u64) -> u32 {
+        // CYCCNT and SysTick usually have the same clock and no ticks
+        // conversion is necessary, only clamping:
+        //
+        // ARM Architecture Reference Manual says:
+        // "Setting SYST_RVR to zero has the effect of
+        // disabling the SysTick counter independently
+        // of the counter enable bit.", so the min is 1
+        // (or the configured minimum).
+        //
+        // SysTick is a 24 bit counter (or the configured maximum).
+        self.to_systick_ticks(ticks)
+            .clamp(self.min_reload as _, self.max_reload as _) as _
+    }
+
+    /// Cycle counter ticks from now until `val`, zero if it is in the past.
+    #[inline(always)]
+    fn ticks_until(&mut self, val: <Self as Monotonic>::Instant) -> u64 {
+        // The input `val` refers to the cycle counter value (up-counter)
+        // but the SysTick is a down-counter with interrupt on zero.
+        val.checked_duration_since(self.now())
+            // Minimum reload value if `val` is in the past
+            .map_or(0, |duration| self.unscaled(duration.ticks() as _))
+    }
+
+    /// Apply the `set_time_scale()` scale to the real instant `now`.
+    #[inline(always)]
+    fn scaled(&self, now: <Self as Monotonic>::Instant) -> <Self as Monotonic>::Instant {
+        <Self as Monotonic>::Instant::from_ticks(self.scale(now.ticks() as _) as _)
+    }
+
+    /// Scaled time for `ticks` real cycle counter ticks.
+    #[inline(always)]
+    fn scale(&self, ticks: u64) -> u64 {
+        #[cfg(debug_assertions)]
+        if self.time_scale != (1, 1) {
+            let (num, den) = self.time_scale;
+            return muldiv(ticks, num, den);
+        }
+
+        ticks
+    }
+
+    /// Real cycle counter ticks for `ticks` ticks of scaled time.
+    #[inline(always)]
+    fn unscaled(&self, ticks: u64) -> u64 {
+        #[cfg(debug_assertions)]
+        if self.time_scale != (1, 1) {
+            let (num, den) = self.time_scale;
+            return mul_div_rounded(ticks, den, num, Rounding::Ceil);
         }
+
+        ticks
+    }
+
+    /// Remember when the SysTick, counting down from `reload`, will fire.
+    #[inline(always)]
+    fn record_armed(&mut self, reload: u32) {
+        // The reload is loaded one SysTick clock after clearing.
+        let ticks = reload as u64 + 1;
+        self.armed = self.peek_ticks().wrapping_add(self.to_cycles(ticks));
     }
 }
 
-impl<const TIMER_HZ: u32> Monotonic for DwtSystick<TIMER_HZ> {
+impl<S: CycleSource, const TIMER_HZ: u32> Monotonic for GenericCycleMonotonic<S, TIMER_HZ> {
     cfg_if::cfg_if! {
         if #[cfg(not(feature = "extend"))] {
             const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = true;
@@ -72,7 +1885,7 @@ impl<const TIMER_HZ: u32> Monotonic for DwtSystick<TIMER_HZ> {
 
             #[inline(always)]
             fn now(&mut self) -> Self::Instant {
-                Self::Instant::from_ticks(DWT::cycle_count())
+                self.scaled(Self::Instant::from_ticks(self.source.read()))
             }
         } else {
             // Need to detect and track overflows.
@@ -83,51 +1896,61 @@ impl<const TIMER_HZ: u32> Monotonic for DwtSystick<TIMER_HZ> {
 
             #[inline(always)]
             fn now(&mut self) -> Self::Instant {
-                let mut high = (self.last >> 32) as u32;
-                let low = self.last as u32;
-                let now = DWT::cycle_count();
-
-                // Detect CYCCNT overflow
-                if now < low {
-                    high = high.wrapping_add(1);
-                }
-                self.last = ((high as u64) << 32) | (now as u64);
-
-                Self::Instant::from_ticks(self.last)
+                let now = self.track(self.source.read());
+                self.scaled(now)
             }
         }
     }
 
     unsafe fn reset(&mut self) {
+        if let Some(reload) = self.initial_reload {
+            self.systick.set_reload(reload);
+            self.systick.clear_current();
+        }
+
         self.systick.enable_counter();
 
-        // Enable and reset the cycle counter to locate the epoch.
-        self.dwt.enable_cycle_counter();
-        self.dwt.set_cycle_count(0);
+        if self.keep_count {
+            // Continue counting from the current value.
+            self.source.start();
+        } else {
+            // Enable and reset the cycle counter to locate the epoch.
+            self.source.reset();
+        }
+
+        if let Some(reload) = self.initial_reload {
+            self.record_armed(reload);
+            self.systick.enable_interrupt();
+        }
+
+        self.started = true;
     }
 
     fn set_compare(&mut self, val: Self::Instant) {
-        // The input `val` refers to the cycle counter value (up-counter)
-        // but the SysTick is a down-counter with interrupt on zero.
-        let reload = val
-            .checked_duration_since(self.now())
-            // Minimum reload value if `val` is in the past
-            .map_or(0, |duration| duration.ticks())
-            // CYCCNT and SysTick have the same clock, no
-            // ticks conversion necessary, only clamping:
-            //
-            // ARM Architecture Reference Manual says:
-            // "Setting SYST_RVR to zero has the effect of
-            // disabling the SysTick counter independently
-            // of the counter enable bit.", so the min is 1
-            .max(1)
-            // SysTick is a 24 bit counter.
-            .min(0xff_ffff) as u32;
+        #[cfg(feature = "diagnostics")]
+        let start = self.source.read();
 
-        self.systick.set_reload(reload);
-        // Also clear the current counter. That doesn't cause a SysTick
-        // interrupt and loads the reload value on the next cycle.
-        self.systick.clear_current();
+        let reload = self.ticks_until(val);
+        self.arm(reload);
+
+        // A deadline beyond the SysTick window is approached in steps of
+        // the maximum reload, see `clear_compare_flag()`.
+        #[cfg(feature = "extend")]
+        if self.to_systick_ticks(reload) > self.max_reload as u64 {
+            self.long_target = Some(val.ticks());
+        }
+
+        #[cfg(feature = "diagnostics")]
+        {
+            // Exponential moving average over about 16 calls, kept scaled by 16.
+            let cycles = self.source.read().wrapping_sub(start);
+            let avg = &mut self.set_compare_cycles;
+            *avg = if *avg == 0 {
+                cycles.saturating_mul(16)
+            } else {
+                (*avg - *avg / 16).saturating_add(cycles)
+            };
+        }
     }
 
     #[inline(always)]
@@ -147,15 +1970,582 @@ impl<const TIMER_HZ: u32> Monotonic for DwtSystick<TIMER_HZ> {
         // interval.
         #[cfg(feature = "extend")]
         {
-            self.systick.set_reload(0xff_ffff);
-            self.systick.clear_current();
+            // Continue towards a deadline beyond the SysTick window. It stays
+            // pending through the final step until the deadline is reached.
+            if let Some(target) = self.long_target {
+                if let Some(remaining @ 1..) = target.checked_sub(self.now().ticks()) {
+                    let remaining = self.unscaled(remaining);
+                    self.arm(remaining);
+                    self.long_target = Some(target);
+                    return;
+                }
+                self.long_target = None;
+            }
+
+            // The SysTick is not used when polling.
+            if !self.polling {
+                self.load(self.max_reload);
+            }
         }
     }
 
-    #[cfg(feature = "extend")]
     fn on_interrupt(&mut self) {
         // Ensure `now()` is called regularly to track overflows.
         // Since SysTick is narrower than CYCCNT, this is sufficient.
+        #[cfg(feature = "extend")]
         self.now();
+
+        if self.pendsv {
+            SCB::set_pendsv();
+        }
+    }
+}
+
+/// Poll the counter read by `read` until `ticks` have elapsed, accumulating the
+/// elapsed ticks so counter overflows don't matter.
+#[inline(always)]
+fn wait_ticks(ticks: u64, mut read: impl FnMut() -> u32) {
+    let mut remaining = ticks;
+    let mut last = read();
+    while remaining > 0 {
+        let now = read();
+        remaining = remaining.saturating_sub(now.wrapping_sub(last) as u64);
+        last = now;
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    /// Host cycle counter advancing by `step` on every read.
+    struct MockSource {
+        count: Cell<u32>,
+        step: u32,
+    }
+
+    impl CycleSource for MockSource {
+        fn read(&self) -> u32 {
+            let count = self.count.get();
+            self.count.set(count.wrapping_add(self.step));
+            count
+        }
+
+        fn start(&mut self) {}
+
+        fn reset(&mut self) {
+            self.count.set(0);
+        }
+
+        fn set(&mut self, value: u32) {
+            self.count.set(value);
+        }
+    }
+
+    type Mono<const HZ: u32> = GenericCycleMonotonic<MockSource, HZ>;
+    type Instant = <Mono<1_000_000> as Monotonic>::Instant;
+    type Duration = <Mono<1_000_000> as Monotonic>::Duration;
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "extend")] {
+            type Ticks = u64;
+        } else {
+            type Ticks = u32;
+        }
+    }
+
+    /// A monotonic on a mock source. The SysTick registers must not be used.
+    fn mono<const HZ: u32>(count: u32, step: u32) -> Mono<HZ> {
+        let source = MockSource {
+            count: Cell::new(count),
+            step,
+        };
+        // NOTE(unsafe): only the register-free parts are tested
+        GenericCycleMonotonic::init(source, unsafe { cortex_m::Peripherals::steal() }.SYST)
+    }
+
+    #[cfg(feature = "extend")]
+    #[test]
+    fn extend_carries_on_wrap() {
+        assert_eq!(extend(0xffff_fff0, 0x10), 0x1_0000_0010);
+        assert_eq!(extend(5 << 32 | 100, 200), 5 << 32 | 200);
+    }
+
+    #[cfg(feature = "extend")]
+    #[test]
+    fn extend_saturates() {
+        assert_eq!(extend(u64::MAX - 5, 0), u64::MAX);
+        assert_eq!(extend(u64::MAX, 3), u64::MAX);
+        assert_eq!(extend(u64::MAX, u32::MAX), u64::MAX);
+        assert_eq!(extend(u64::MAX - 5, u32::MAX - 1), u64::MAX - 1);
+    }
+
+    #[cfg(feature = "extend")]
+    #[test]
+    fn now_tracks_wrap_at_u32_max() {
+        let mut mono = mono::<1_000_000>(u32::MAX - 10, 0);
+        assert_eq!(mono.now().ticks(), (u32::MAX - 10) as u64);
+
+        mono.source.set(u32::MAX);
+        assert_eq!(mono.now().ticks(), u32::MAX as u64);
+
+        mono.source.set(5);
+        assert_eq!(mono.now().ticks(), 1 << 32 | 5);
+        assert_eq!(mono.now_raw(), (Instant::from_ticks(1 << 32 | 5), 5));
+    }
+
+    #[cfg(feature = "extend")]
+    #[test]
+    fn next_overflow_instant() {
+        let mut mono = mono::<1_000_000>(5, 0);
+        assert_eq!(mono.next_overflow_instant().ticks(), 1 << 32);
+
+        mono.source.set(u32::MAX);
+        assert_eq!(mono.next_overflow_instant().ticks(), 1 << 32);
+
+        mono.source.set(0);
+        assert_eq!(mono.next_overflow_instant().ticks(), 2 << 32);
+    }
+
+    #[test]
+    fn past_deadline_arms_minimum_reload() {
+        let mut mono = mono::<1_000_000>(1_000, 0);
+        assert_eq!(mono.ticks_until(Instant::from_ticks(500)), 0);
+        assert_eq!(mono.systick_reload(0), 1);
+        assert_eq!(mono.ticks_until(Instant::from_ticks(3_000)), 2_000);
+    }
+
+    #[test]
+    fn reload_clamps_to_systick_range() {
+        let mut mono = mono::<1_000_000>(0, 0);
+        assert_eq!(mono.systick_reload(1_000), 1_000);
+        assert_eq!(mono.systick_reload(0x100_0000), 0xff_ffff);
+        assert_eq!(mono.systick_reload(u64::MAX), 0xff_ffff);
+
+        mono.set_min_reload(50);
+        mono.set_max_reload(1_000);
+        assert_eq!(mono.systick_reload(0), 50);
+        assert_eq!(mono.systick_reload(5_000), 1_000);
+    }
+
+    #[test]
+    fn reference_clock_range() {
+        for hz in [1_000_000, 7_813] {
+            assert_eq!(
+                Mono::<1_000_000>::check_reference_clock(hz),
+                Ok(NonZeroU32::new(hz).unwrap())
+            );
+        }
+        for hz in [0, 7_812, 1_000_001] {
+            assert_eq!(
+                Mono::<1_000_000>::check_reference_clock(hz),
+                Err(InitError::ReferenceClock)
+            );
+        }
+    }
+
+    #[test]
+    fn reload_scales_to_reference_clock() {
+        let mut mono = mono::<1_000_000>(0, 0);
+        mono.reference_hz = NonZeroU32::new(125_000);
+        assert_eq!(mono.systick_reload(800), 100);
+        assert_eq!(mono.systick_reload(801), 101);
+    }
+
+    #[test]
+    fn reload_for_hz() {
+        let mono = mono::<1_000_000>(0, 0);
+        assert_eq!(mono.reload_for_hz(1_000), Ok(999));
+        assert_eq!(mono.reload_for_hz(1), Ok(999_999));
+        assert_eq!(mono.reload_for_hz(0), Err(ReloadError::TooSlow));
+        assert_eq!(mono.reload_for_hz(1_000_000), Err(ReloadError::TooFast));
+
+        let fast = self::mono::<100_000_000>(0, 0);
+        assert_eq!(fast.reload_for_hz(1), Err(ReloadError::TooSlow));
+    }
+
+    /// A counter starting at `start` and advancing by `step` per read.
+    fn counter(start: u32, step: u32) -> impl FnMut() -> u32 {
+        let mut count = start.wrapping_sub(step);
+        move || {
+            count = count.wrapping_add(step);
+            count
+        }
+    }
+
+    #[test]
+    fn delay_waits_across_wrap() {
+        let start = u32::MAX - 20;
+        let mut reads = 0;
+        let mut read = counter(start, 7);
+        let mut last = start;
+        wait_ticks(Duration::from_ticks(1_000).ticks() as _, || {
+            reads += 1;
+            last = read();
+            last
+        });
+
+        let elapsed = last.wrapping_sub(start);
+        assert!((1_000..1_000 + 7).contains(&elapsed), "{}", elapsed);
+        assert_eq!(reads, 1 + 1_000_u32.div_ceil(7));
+    }
+
+    #[test]
+    fn delay_longer_than_counter_range() {
+        let mut reads = 0;
+        let mut read = counter(0, 1 << 30);
+        wait_ticks(5 << 30, || {
+            reads += 1;
+            read()
+        });
+        // One read for the start and five more, wrapping the counter once.
+        assert_eq!(reads, 6);
+    }
+
+    #[test]
+    fn delay_on_mock_source() {
+        let start = u32::MAX - 20;
+        let mono = mono::<1_000_000>(start, 7);
+        mono.delay(Duration::from_ticks(1_000));
+
+        let elapsed = mono.source.count.get().wrapping_sub(start);
+        assert!((1_000..1_000 + 3 * 7).contains(&elapsed), "{}", elapsed);
+    }
+
+    #[test]
+    fn cycles_between_raw_without_wraps() {
+        assert_eq!(cycles_between_raw(100, 350, 0), 250);
+        assert_eq!(cycles_between_raw(7, 7, 0), 0);
+        assert_eq!(cycles_between_raw(350, 100, 0), 0);
+    }
+
+    #[test]
+    fn cycles_between_raw_one_wrap() {
+        assert_eq!(cycles_between_raw(0xffff_fff0, 0x10, 1), 0x20);
+        assert_eq!(cycles_between_raw(100, 100, 1), 1 << 32);
+        assert_eq!(cycles_between_raw(0, u32::MAX, 1), (1 << 33) - 1);
+    }
+
+    #[test]
+    fn cycles_between_raw_multiple_wraps() {
+        assert_eq!(cycles_between_raw(0xffff_fff0, 0x10, 3), (2 << 32) + 0x20);
+        assert_eq!(cycles_between_raw(0, u32::MAX, u32::MAX), u64::MAX);
+        assert_eq!(
+            cycles_between_raw(u32::MAX, 0, u32::MAX),
+            (u64::MAX << 32) - u32::MAX as u64
+        );
+    }
+
+    #[test]
+    fn core_duration_to_ticks() {
+        let ticks = Mono::<1_000_000>::core_duration_to_ticks;
+        assert_eq!(ticks(core::time::Duration::from_millis(3)), 3_000);
+        assert_eq!(ticks(core::time::Duration::new(2, 500)), 2_000_001);
+        assert_eq!(ticks(core::time::Duration::from_nanos(1)), 1);
+        assert_eq!(ticks(core::time::Duration::ZERO), 0);
+        assert_eq!(ticks(core::time::Duration::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn checked_add_at_boundary() {
+        let near = Instant::from_ticks(Ticks::MAX - 1);
+        assert_eq!(
+            Mono::<1_000_000>::checked_add(near, Duration::from_ticks(1)),
+            Some(Instant::from_ticks(Ticks::MAX))
+        );
+        assert_eq!(
+            Mono::<1_000_000>::checked_add(near, Duration::from_ticks(2)),
+            None
+        );
+        assert_eq!(
+            Mono::<1_000_000>::checked_add(
+                Instant::from_ticks(0),
+                Duration::from_ticks(Ticks::MAX)
+            ),
+            Some(Instant::from_ticks(Ticks::MAX))
+        );
+    }
+
+    #[test]
+    fn saturating_sub_at_epoch() {
+        let sub = Mono::<1_000_000>::saturating_sub;
+        let instant = Instant::from_ticks(10);
+        assert_eq!(
+            sub(instant, Duration::from_ticks(4)),
+            Instant::from_ticks(6)
+        );
+        assert_eq!(
+            sub(instant, Duration::from_ticks(11)),
+            Instant::from_ticks(0)
+        );
+    }
+
+    #[test]
+    fn mul_div_rounding() {
+        assert_eq!(mul_div_rounded(10, 1, 3, Rounding::Floor), 3);
+        assert_eq!(mul_div_rounded(10, 1, 3, Rounding::Nearest), 3);
+        assert_eq!(mul_div_rounded(10, 1, 3, Rounding::Ceil), 4);
+        assert_eq!(mul_div_rounded(5, 1, 2, Rounding::Nearest), 3);
+        assert_eq!(mul_div_rounded(9, 1, 3, Rounding::Ceil), 3);
+    }
+
+    #[test]
+    fn muldiv_extremes() {
+        let exact =
+            |value: u64, num: u32, den: u32| (value as u128 * num as u128 / den as u128) as u64;
+
+        assert_eq!(muldiv(u64::MAX / 3, 3, 1), u64::MAX / 3 * 3);
+        assert_eq!(muldiv(u64::MAX / 3 + 1, 3, 1), u64::MAX);
+        assert_eq!(muldiv(u64::MAX, 2, 1), u64::MAX);
+        assert_eq!(muldiv(u64::MAX, 3, 3), u64::MAX);
+        assert_eq!(muldiv(u64::MAX, u32::MAX, u32::MAX), u64::MAX);
+
+        let num = 999_999_937;
+        let value = u64::MAX / num as u64;
+        for value in [value - 1, value, value + 1] {
+            assert_eq!(
+                muldiv(value, num, 1_000_000_000),
+                exact(value, num, 1_000_000_000)
+            );
+            assert_eq!(muldiv(value, num, u32::MAX), exact(value, num, u32::MAX));
+            assert_eq!(muldiv(value, num, num + 1), exact(value, num, num + 1));
+        }
+        assert_eq!(
+            muldiv(u64::MAX - 1, 1_000, 1_000_000_000),
+            u64::MAX / 1_000_000
+        );
+        assert_eq!(muldiv(0, u32::MAX, 1), 0);
+    }
+
+    #[test]
+    fn rescale_after_clock_doubling() {
+        let mut mono = mono::<2_000>(1_000, 0);
+        mono.rescale_after_clock_change(1_000, 2_000);
+        assert_eq!(mono.source.count.get(), 2_000);
+    }
+
+    #[cfg(feature = "extend")]
+    #[test]
+    fn rescale_after_clock_doubling_keeps_overflows() {
+        let mut mono = mono::<2_000>(1 << 31, 0);
+        mono.last = 1 << 32;
+        mono.rescale_after_clock_change(1_000, 2_000);
+        assert_eq!(mono.last, 3 << 32);
+        assert_eq!(mono.source.count.get(), 0);
+        assert_eq!(mono.now().ticks(), 3 << 32);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn time_scale_keeps_real_compares() {
+        let mut mono = mono::<1_000_000>(1_000, 0);
+        mono.set_time_scale(3_600, 1);
+        let now = mono.now();
+        assert_eq!(now.ticks(), 3_600_000);
+        assert_eq!(mono.ticks_until(now + Duration::from_ticks(7_200)), 2);
+        assert_eq!(mono.ticks_until(now + Duration::from_ticks(7_201)), 3);
+
+        mono.set_time_scale(2, 3);
+        assert_eq!(mono.now().ticks(), 666);
+        assert_eq!(mono.ticks_until(Instant::from_ticks(766)), 150);
+    }
+
+    #[test]
+    fn rescale_after_clock_halving() {
+        let mut mono = mono::<1_000>(2_001, 0);
+        mono.rescale_after_clock_change(2_000, 1_000);
+        assert_eq!(mono.source.count.get(), 1_000);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn time_scale_covers_raw_paths() {
+        let mut mono = mono::<1_000_000>(1_000, 0);
+        mono.set_time_scale(3_600, 1);
+        let (base, raw) = mono.now_raw();
+        assert_eq!((base.ticks(), raw), (3_600_000, 1_000));
+        assert_eq!(mono.ticks_after(base, Duration::from_ticks(7_200)), 2);
+
+        mono.source.set(1_001);
+        assert_eq!(mono.ticks_after(base, Duration::from_ticks(7_200)), 1);
+        assert_eq!(mono.ticks_after(base, Duration::from_ticks(3_600)), 0);
+
+        mono.armed = 1_002;
+        assert_eq!(mono.armed_deadline().ticks(), 3_607_200);
+        assert_eq!(TimestampSource::now_ticks(&mono), 3_603_600);
+
+        mono.checkpoint();
+        mono.source.set(1_003);
+        assert_eq!(mono.since_checkpoint(), Duration::from_ticks(7_200));
+    }
+
+    #[test]
+    fn now_before_reset() {
+        let mut mono = mono::<1_000_000>(1_000, 0);
+        assert!(!mono.is_started());
+        assert_eq!(mono.try_now(), None);
+
+        mono.started = true;
+        assert_eq!(mono.try_now(), Some(Instant::from_ticks(1_000)));
+    }
+
+    #[cfg(feature = "extend")]
+    #[test]
+    fn compare_pending_until_long_deadline() {
+        let mut mono = mono::<1_000_000>(0, 0);
+        mono.start_polling();
+        mono.set_max_reload(1_000);
+        mono.set_compare(Instant::from_ticks(2_500));
+        assert!(mono.compare_pending());
+
+        for (now, pending) in [(1_000, true), (2_000, true), (2_500, false)] {
+            mono.source.set(now);
+            mono.clear_compare_flag();
+            assert_eq!(mono.compare_pending(), pending, "at {now}");
+        }
+    }
+
+    #[test]
+    fn polling_starts_at_construction() {
+        let mut mono = mono::<1_000_000>(1_000, 0);
+        mono.start_polling();
+        assert!(mono.is_started());
+        assert_eq!(mono.try_now(), Some(Instant::from_ticks(0)));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut original = mono::<1_000_000>(5_000, 0);
+        original.started = true;
+        original.armed = 6_000;
+        original.set_max_reload(2_000);
+        original.set_min_reload(10);
+        #[cfg(feature = "extend")]
+        {
+            original.long_target = Some(1 << 40);
+        }
+        let s = original.snapshot();
+
+        let mut other = mono::<1_000_000>(0, 0);
+        other.start_polling();
+        other.armed = 100;
+        // NOTE(unsafe): polling, the SysTick is not touched
+        unsafe { other.restore(s) };
+        assert_eq!(other.snapshot(), s);
+        assert_eq!(other.now().ticks(), 5_000);
+        assert_eq!(other.armed_deadline(), Instant::from_ticks(6_000));
+    }
+
+    #[test]
+    fn since_epoch_past_half_range() {
+        let mut mono = mono::<1_000_000>(0x8000_0001, 0);
+        assert_eq!(mono.since_epoch(), Duration::from_ticks(0x8000_0001));
+        assert!(mono.elapsed_exceeds(Duration::from_ticks(0x8000_0000)));
+        assert!(!mono.elapsed_exceeds(Duration::from_ticks(0x8000_0001)));
+    }
+
+    #[test]
+    fn since_checkpoint() {
+        let mut mono = mono::<1_000_000>(u32::MAX - 100, 0);
+        mono.checkpoint();
+        mono.source.set(149);
+        assert_eq!(mono.since_checkpoint(), Duration::from_ticks(250));
+    }
+
+    #[test]
+    fn phase_in_period() {
+        let mut mono = mono::<1_000_000>(1_234, 0);
+        assert_eq!(mono.phase_in_period(100), 34);
+        assert_eq!(mono.phase_fraction(100), 22_282);
+    }
+
+    #[test]
+    fn trim_corrects_conversions() {
+        let mut mono = mono::<1_000_000>(1_001_000, 0);
+        mono.set_trim_ppm(1_000);
+        assert_eq!(mono.now_nanos(), 1_000_000_000);
+        assert_eq!(mono.seconds(), 1);
+
+        mono.set_trim_ppm(0);
+        assert_eq!(mono.system_ticks_ms(), 1_001);
+    }
+
+    #[test]
+    fn hms_carries() {
+        let mut mono = mono::<1_000>(3_599_999, 0);
+        assert_eq!(mono.hms(), (0, 59, 59, 999));
+
+        mono.source.set(3_600_000);
+        assert_eq!(mono.hms(), (1, 0, 0, 0));
+
+        mono.source.set(3_723_456);
+        assert_eq!(mono.hms(), (1, 2, 3, 456));
+    }
+
+    #[test]
+    fn stamped_instants_compare_across_rates() {
+        let a = StampedInstant {
+            ticks: 1_000,
+            hz: 1_000,
+        };
+        let b = StampedInstant {
+            ticks: 2_000,
+            hz: 2_000,
+        };
+        assert_eq!(a, b);
+        assert!(
+            StampedInstant {
+                ticks: 999,
+                hz: 1_000
+            } < b
+        );
+        assert_eq!(b.rescale(1_000), 1_000);
+    }
+
+    #[test]
+    fn coarse_timestamps() {
+        let mut mono = mono::<1_000_000>(1_000, 0);
+        type Coarse = CoarseMono<1_000_000, 4>;
+        assert_eq!(Coarse::now(&mut mono), 62);
+        assert_eq!(Coarse::to_ticks(62), 992);
+        assert_eq!(Coarse::to_micros(62), 992);
+        assert_eq!(Coarse::HZ, 62_500);
+    }
+
+    #[test]
+    fn deadline_buckets() {
+        type Ms = Buckets<1_000_000, 1_000>;
+        assert_eq!(Ms::TICKS, 1_000);
+        assert_eq!(Ms::index(Instant::from_ticks(12_345)), 12);
+        assert_eq!(Ms::index(Instant::from_ticks(999)), 0);
+        assert_eq!(Ms::start(12), Instant::from_ticks(12_000));
+
+        let (a, b) = (Instant::from_ticks(999), Instant::from_ticks(2_000));
+        assert_eq!(Ms::buckets_between(a, b), 2);
+        assert_eq!(Ms::buckets_between(b, a), -2);
+        assert_eq!(Ms::buckets_between(a, a), 0);
+
+        let (c, d) = (Instant::from_ticks(1_000), Instant::from_ticks(1_999));
+        assert_eq!(Ms::buckets_between(c, d), 0);
+        assert_eq!(Ms::buckets_between(d, c), 0);
+        assert_eq!(Ms::buckets_between(a, c), 1);
+        assert_eq!(Ms::buckets_between(c, a), -1);
+    }
+
+    #[test]
+    fn deadline_buckets_across_wrap() {
+        type Ms = Buckets<1_000_000, 1_000>;
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "extend")] {
+                let (a, b) = (Instant::from_ticks(u32::MAX as u64 - 499), Instant::from_ticks(1 << 32 | 1_500));
+                assert_eq!(Ms::index(b), (1 << 32 | 1_500) / 1_000);
+            } else {
+                let (a, b) = (Instant::from_ticks(u32::MAX - 499), Instant::from_ticks(1_500));
+            }
+        }
+        assert_eq!(Ms::buckets_between(a, b), 2);
+        assert_eq!(Ms::buckets_between(b, a), -2);
     }
 }
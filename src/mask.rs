@@ -0,0 +1,64 @@
+//! Scoped masking of the SysTick exception
+
+use crate::{CycleSource, GenericCycleMonotonic};
+use core::ops::{Deref, DerefMut};
+
+/// Guard returned by `GenericCycleMonotonic::mask_systick()`.
+///
+/// The SysTick exception is disabled while the guard lives and restored to
+/// its previous state on drop. The monotonic stays accessible through the
+/// guard. With the `diagnostics` feature, dropping the guard asserts that the
+/// section lasted less than half the cycle counter period, beyond which the
+/// `extend` overflow tracking can be corrupted.
+pub struct SysTickMaskGuard<'a, S: CycleSource, const TIMER_HZ: u32> {
+    mono: &'a mut GenericCycleMonotonic<S, TIMER_HZ>,
+    enabled: bool,
+    #[cfg(feature = "diagnostics")]
+    start: u32,
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> GenericCycleMonotonic<S, TIMER_HZ> {
+    /// Disable the SysTick exception until the returned guard is dropped.
+    pub fn mask_systick(&mut self) -> SysTickMaskGuard<'_, S, TIMER_HZ> {
+        let enabled = self.systick.is_interrupt_enabled();
+        self.disable_interrupt();
+
+        SysTickMaskGuard {
+            #[cfg(feature = "diagnostics")]
+            start: self.source.read(),
+            mono: self,
+            enabled,
+        }
+    }
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> Deref for SysTickMaskGuard<'_, S, TIMER_HZ> {
+    type Target = GenericCycleMonotonic<S, TIMER_HZ>;
+
+    fn deref(&self) -> &Self::Target {
+        self.mono
+    }
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> DerefMut for SysTickMaskGuard<'_, S, TIMER_HZ> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.mono
+    }
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> Drop for SysTickMaskGuard<'_, S, TIMER_HZ> {
+    fn drop(&mut self) {
+        #[cfg(feature = "diagnostics")]
+        {
+            let elapsed = self.mono.source.read().wrapping_sub(self.start);
+            debug_assert!(
+                elapsed <= i32::MAX as u32,
+                "SysTick masked for more than half the cycle counter period"
+            );
+        }
+
+        if self.enabled {
+            self.mono.enable_interrupt();
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Drift-free periodic deadlines
+
+use crate::{CycleSource, Duration, GenericCycleMonotonic, Instant};
+use rtic_monotonic::Monotonic;
+
+/// Periodic deadline generator for `DwtSystick` or `GenericCycleMonotonic`.
+///
+/// Each deadline is the previous one advanced by exactly one period, not
+/// `now()` plus the period, so no drift accumulates.
+pub struct Periodic<const TIMER_HZ: u32> {
+    deadline: Instant<TIMER_HZ>,
+    period: Duration<TIMER_HZ>,
+}
+
+impl<const TIMER_HZ: u32> Periodic<TIMER_HZ> {
+    /// Create a new periodic schedule with the first deadline at `start + period`.
+    ///
+    /// Panics if `period` is zero.
+    pub fn new(start: Instant<TIMER_HZ>, period: Duration<TIMER_HZ>) -> Self {
+        assert!(period.ticks() > 0);
+
+        Periodic {
+            deadline: start,
+            period,
+        }
+    }
+
+    /// The period between deadlines.
+    #[inline(always)]
+    pub fn period(&self) -> Duration<TIMER_HZ> {
+        self.period
+    }
+
+    /// The most recent deadline returned by `next()` (or the start instant).
+    #[inline(always)]
+    pub fn deadline(&self) -> Instant<TIMER_HZ> {
+        self.deadline
+    }
+
+    /// Advance to the next deadline and arm the compare for it.
+    ///
+    /// Deadlines that have already passed are skipped by whole periods so a
+    /// late caller catches up without a burst of immediate expirations.
+    pub fn next<S: CycleSource>(
+        &mut self,
+        mono: &mut GenericCycleMonotonic<S, TIMER_HZ>,
+    ) -> Instant<TIMER_HZ> {
+        let now = mono.now();
+        let mut deadline = self.deadline + self.period;
+
+        if now > deadline {
+            let missed = (now - deadline).ticks() / self.period.ticks() + 1;
+            deadline += Duration::<TIMER_HZ>::from_ticks(missed * self.period.ticks());
+        }
+
+        self.deadline = deadline;
+        mono.set_compare(deadline);
+        deadline
+    }
+}
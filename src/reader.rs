@@ -0,0 +1,54 @@
+//! Copyable read-only handle to the time
+
+use crate::{global, DwtSystick, Instant, TimestampSource};
+
+/// Read-only handle to the time of a `DwtSystick`, see `time_reader()`.
+///
+/// The monotonic keeps the SysTick and is handed to RTIC for scheduling. The
+/// reader only reads `CYCCNT` and, with the `extend` feature, the overflows
+/// that the monotonic publishes to a static on each `now()`. It is `Copy` and
+/// can be stored in any resource or used from any context.
+///
+/// The published high word is stored as an atomic in units of half a counter
+/// period (`2^31` ticks). A reading combines it with `CYCCNT` and is correct as
+/// long as the monotonic's `now()` ran within the last half period, which the
+/// SysTick interrupt ensures. No 64-bit atomic or critical section is needed.
+#[derive(Debug, Clone, Copy)]
+pub struct TimeReader<const TIMER_HZ: u32> {
+    _registered: (),
+}
+
+impl<const TIMER_HZ: u32> TimeReader<TIMER_HZ> {
+    /// The current time, the same as the monotonic's `now()`.
+    #[inline(always)]
+    pub fn now(&self) -> Instant<TIMER_HZ> {
+        Instant::<TIMER_HZ>::from_ticks(global::shared_ticks() as _)
+    }
+}
+
+impl<const TIMER_HZ: u32> TimestampSource for TimeReader<TIMER_HZ> {
+    #[inline(always)]
+    fn now_ticks(&self) -> u64 {
+        global::shared_ticks()
+    }
+
+    #[inline(always)]
+    fn hz(&self) -> u32 {
+        TIMER_HZ
+    }
+}
+
+impl<const TIMER_HZ: u32> DwtSystick<TIMER_HZ> {
+    /// Split off a `Copy` handle reading the time through a shared reference.
+    ///
+    /// This registers the monotonic with `register_global()` if nothing is
+    /// registered yet. It returns `None` if another monotonic is registered,
+    /// as the reader would follow that one.
+    pub fn time_reader(&mut self) -> Option<TimeReader<TIMER_HZ>> {
+        if !self.global && !self.register_global() {
+            return None;
+        }
+
+        Some(TimeReader { _registered: () })
+    }
+}
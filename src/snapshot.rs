@@ -0,0 +1,73 @@
+//! Capturing and restoring the monotonic state
+
+use crate::{CycleSource, GenericCycleMonotonic};
+
+/// The complete state of a `GenericCycleMonotonic`, see `snapshot()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MonoSnapshot {
+    /// The tick count, including the overflows tracked with the `extend`
+    /// feature in the high word.
+    pub ticks: u64,
+    /// The cycle counter tick the SysTick is armed to fire at.
+    pub armed: u64,
+    /// The deadline beyond the SysTick window being approached with the
+    /// `extend` feature.
+    #[cfg(feature = "extend")]
+    pub long_target: Option<u64>,
+    /// The minimum SysTick reload, see `set_min_reload()`.
+    pub min_reload: u32,
+    /// The maximum SysTick reload, see `set_max_reload()`.
+    pub max_reload: u32,
+    /// Whether the monotonic was started.
+    pub started: bool,
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> GenericCycleMonotonic<S, TIMER_HZ> {
+    /// Capture the tick count and the armed compare.
+    ///
+    /// This is intended for record/replay debugging together with `restore()`.
+    /// It only reads the cycle counter, not the SysTick.
+    #[inline(always)]
+    pub fn snapshot(&self) -> MonoSnapshot {
+        MonoSnapshot {
+            ticks: self.peek_ticks(),
+            armed: self.armed,
+            #[cfg(feature = "extend")]
+            long_target: self.long_target,
+            min_reload: self.min_reload,
+            max_reload: self.max_reload,
+            started: self.started,
+        }
+    }
+
+    /// Restore a state captured by `snapshot()`.
+    ///
+    /// The cycle counter is set to the low word of the tick count. The phase
+    /// of the SysTick can't be restored, so unless polling it is re-armed for
+    /// the time remaining from the captured tick count to the armed compare.
+    ///
+    /// # Safety
+    ///
+    /// This moves the time backwards or forwards. On real hardware this breaks
+    /// the monotonicity that RTIC and other users of the timer rely on.
+    pub unsafe fn restore(&mut self, s: MonoSnapshot) {
+        self.source.set(s.ticks as u32);
+
+        #[cfg(feature = "extend")]
+        {
+            self.last = s.ticks;
+            self.long_target = s.long_target;
+        }
+
+        self.min_reload = s.min_reload;
+        self.max_reload = s.max_reload;
+        self.started = s.started;
+        self.armed = s.armed;
+
+        if !self.polling {
+            let reload = self.systick_reload(s.armed.saturating_sub(s.ticks));
+            self.systick.set_reload(reload);
+            self.systick.clear_current();
+        }
+    }
+}
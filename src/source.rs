@@ -0,0 +1,178 @@
+//! Cycle counter sources
+
+use crate::{InitError, DCB_DEMCR_TRCENA};
+use core::marker::PhantomData;
+use cortex_m::peripheral::{DCB, DWT};
+
+/// A free-running 32-bit up-counter driving a `GenericCycleMonotonic`.
+///
+/// The counter must run at `TIMER_HZ`, the same clock as the SysTick, and wrap
+/// from `u32::MAX` to zero.
+pub trait CycleSource {
+    /// Read the current counter value.
+    ///
+    /// This must be a volatile read so busy-waits polling it are not
+    /// optimized away.
+    fn read(&self) -> u32;
+
+    /// Start the counter without changing its value.
+    fn start(&mut self);
+
+    /// Start the counter and reset it to zero to locate the epoch.
+    fn reset(&mut self);
+
+    /// Set the counter value.
+    fn set(&mut self, value: u32);
+}
+
+/// The DWT cycle counter (`CYCCNT`) as a `CycleSource`.
+pub struct DwtSource {
+    pub(crate) dwt: DWT,
+    pub(crate) trace_was_enabled: bool,
+    pub(crate) trace_pending: bool,
+}
+
+impl DwtSource {
+    /// Enable trace and the DWT and check for the cycle counter.
+    pub(crate) fn enable(dcb: &mut DCB, dwt: DWT) -> Result<Self, InitError> {
+        // Remember whether some other DWT/ITM user already enabled trace
+        // so `free()` can leave it alone.
+        let trace_was_enabled = dcb.demcr.read() & DCB_DEMCR_TRCENA != 0;
+        dcb.enable_trace();
+        DWT::unlock();
+        if !DWT::has_cycle_counter() {
+            return Err(InitError::NoCycleCounter);
+        }
+
+        Ok(DwtSource {
+            dwt,
+            trace_was_enabled,
+            trace_pending: false,
+        })
+    }
+
+    /// Release the DWT, disabling the cycle counter and trace only if trace
+    /// was not already enabled before.
+    pub(crate) fn free(mut self, dcb: &mut DCB) -> DWT {
+        if !self.trace_was_enabled {
+            self.dwt.disable_cycle_counter();
+            dcb.disable_trace();
+        }
+
+        self.dwt
+    }
+
+    /// Enable trace and the DWT if that was deferred to the start.
+    #[inline(always)]
+    fn enable_trace(&mut self) {
+        if self.trace_pending {
+            // NOTE(unsafe): read-modify-write of DEMCR without owning the DCB.
+            // It must not race other DEMCR writers, like `DCB::enable_trace()`
+            // after `new()`.
+            unsafe { (*DCB::PTR).demcr.modify(|w| w | DCB_DEMCR_TRCENA) };
+            DWT::unlock();
+            self.trace_pending = false;
+        }
+    }
+}
+
+impl CycleSource for DwtSource {
+    #[inline(always)]
+    fn read(&self) -> u32 {
+        DWT::cycle_count()
+    }
+
+    #[inline(always)]
+    fn start(&mut self) {
+        self.enable_trace();
+        self.dwt.enable_cycle_counter();
+    }
+
+    #[inline(always)]
+    fn reset(&mut self) {
+        self.enable_trace();
+        self.dwt.enable_cycle_counter();
+        self.dwt.set_cycle_count(0);
+    }
+
+    #[inline(always)]
+    fn set(&mut self, value: u32) {
+        self.enable_trace();
+        self.dwt.set_cycle_count(value);
+    }
+}
+
+/// The DWT cycle counter shared by several monotonics.
+///
+/// The counter is configured and started once, here, and only read by the
+/// `SharedCycleSource`s borrowed from it, so no user can reset it under the
+/// others. Construct it first and keep it alive for as long as its users (e.g.
+/// with `cortex_m::singleton!` for RTIC). There is only one SysTick, so only
+/// one monotonic of this crate is built on it, with
+/// `GenericCycleMonotonic::from_source(counter.source(), ..)`. The other
+/// sources go to the application's own readers, e.g. its own monotonic on
+/// another timer or code timestamping with `CycleSource::read()`.
+/// `TickedDwtSystick` owns the DWT and can't use a shared counter.
+pub struct SharedCycleCounter {
+    source: DwtSource,
+}
+
+impl SharedCycleCounter {
+    /// Enable the DWT and start the cycle counter from zero.
+    ///
+    /// Panics if the DWT has no cycle counter.
+    #[inline(always)]
+    pub fn new(dcb: &mut DCB, dwt: DWT) -> Self {
+        Self::try_new(dcb, dwt).unwrap()
+    }
+
+    /// Like `new()` but return an error instead of panicking.
+    pub fn try_new(dcb: &mut DCB, dwt: DWT) -> Result<Self, InitError> {
+        let mut source = DwtSource::enable(dcb, dwt)?;
+        source.reset();
+
+        Ok(SharedCycleCounter { source })
+    }
+
+    /// Borrow a read-only source for a monotonic.
+    #[inline(always)]
+    pub fn source(&self) -> SharedCycleSource<'_> {
+        SharedCycleSource {
+            _counter: PhantomData,
+        }
+    }
+
+    /// Release the DWT once no source is borrowed anymore.
+    ///
+    /// Like `DwtSystick::free()`, the cycle counter and trace are only
+    /// disabled if trace was not already enabled before `new()`.
+    #[inline(always)]
+    pub fn free(self, dcb: &mut DCB) -> DWT {
+        self.source.free(dcb)
+    }
+}
+
+/// A read-only `CycleSource` borrowed from a `SharedCycleCounter`.
+///
+/// The counter runs from the construction of the `SharedCycleCounter`, which
+/// is the epoch of all monotonics using it. `start()`, `reset()` and `set()`
+/// do nothing, so e.g. `restore()` leaves the counter untouched.
+pub struct SharedCycleSource<'a> {
+    _counter: PhantomData<&'a ()>,
+}
+
+impl CycleSource for SharedCycleSource<'_> {
+    #[inline(always)]
+    fn read(&self) -> u32 {
+        DWT::cycle_count()
+    }
+
+    #[inline(always)]
+    fn start(&mut self) {}
+
+    #[inline(always)]
+    fn reset(&mut self) {}
+
+    #[inline(always)]
+    fn set(&mut self, _value: u32) {}
+}
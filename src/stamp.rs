@@ -0,0 +1,78 @@
+//! Instants carrying their own tick frequency
+
+use crate::{muldiv, CycleSource, GenericCycleMonotonic};
+use core::cmp::Ordering;
+
+/// A tick count together with its tick frequency.
+///
+/// Unlike the fugit instants, the rate is only known at runtime, so stamps of
+/// different time sources (e.g. the monotonic and an RTC) can be merged into
+/// one timeline. Stamps compare by the time they represent, exactly, by
+/// cross-multiplying with the other stamp's rate.
+#[derive(Debug, Clone, Copy)]
+pub struct StampedInstant {
+    /// The tick count.
+    pub ticks: u64,
+    /// The tick frequency in Hz.
+    pub hz: u32,
+}
+
+impl StampedInstant {
+    /// The tick count converted to a tick frequency of `hz`.
+    ///
+    /// The result is rounded down, so it is accurate to less than one tick at
+    /// `hz`. It saturates at `u64::MAX`.
+    #[inline(always)]
+    pub const fn rescale(self, hz: u32) -> u64 {
+        muldiv(self.ticks, hz, self.hz)
+    }
+
+    /// Convert to a stamp with a tick frequency of `hz`, see `rescale()`.
+    #[inline(always)]
+    pub const fn to_rate(self, hz: u32) -> Self {
+        StampedInstant {
+            ticks: self.rescale(hz),
+            hz,
+        }
+    }
+
+    fn scaled(&self, other: &Self) -> (u128, u128) {
+        (
+            self.ticks as u128 * other.hz as u128,
+            other.ticks as u128 * self.hz as u128,
+        )
+    }
+}
+
+impl PartialEq for StampedInstant {
+    fn eq(&self, other: &Self) -> bool {
+        let (a, b) = self.scaled(other);
+        a == b
+    }
+}
+
+impl Eq for StampedInstant {}
+
+impl PartialOrd for StampedInstant {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for StampedInstant {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, b) = self.scaled(other);
+        a.cmp(&b)
+    }
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> GenericCycleMonotonic<S, TIMER_HZ> {
+    /// The current time as a `StampedInstant` at `TIMER_HZ`.
+    #[inline(always)]
+    pub fn stamp(&mut self) -> StampedInstant {
+        StampedInstant {
+            ticks: self.tick_count(),
+            hz: TIMER_HZ,
+        }
+    }
+}
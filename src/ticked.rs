@@ -0,0 +1,162 @@
+//! Periodic SysTick tick with DWT sub-tick resolution
+
+use crate::{assert_divides, CycleSource, DwtSource, DwtSystick, InitError};
+use cortex_m::peripheral::{syst::SystClkSource, DCB, DWT, SCB, SYST};
+use rtic_monotonic::Monotonic;
+
+/// `Monotonic` with a periodic SysTick tick at `TICK_HZ`, interpolated by the
+/// DWT cycle counter.
+///
+/// Unlike `DwtSystick`, the SysTick is not reprogrammed for each deadline but
+/// interrupts periodically like an RTOS tick. The time is the number of ticks
+/// times the tick period plus the cycles since the last tick, so it has the
+/// full `TIMER_HZ` resolution and never needs overflow detection beyond the
+/// tick interrupt itself. Deadlines are checked on every tick, so they fire
+/// with tick granularity, after at most one tick period of latency.
+///
+/// `TIMER_HZ` must be a multiple of `TICK_HZ` with a tick period of at most
+/// `2^24` cycles, which is checked at compile time.
+pub struct TickedDwtSystick<const TIMER_HZ: u32, const TICK_HZ: u32> {
+    source: DwtSource,
+    systick: SYST,
+    ticks: u64,
+    tick_start: u32,
+}
+
+impl<const TIMER_HZ: u32, const TICK_HZ: u32> TickedDwtSystick<TIMER_HZ, TICK_HZ> {
+    /// The tick period in cycles.
+    pub const PERIOD: u32 = {
+        assert_divides::<TIMER_HZ, TICK_HZ>();
+        let period = TIMER_HZ / TICK_HZ;
+        assert!(
+            period >= 2 && period <= 0x100_0000,
+            "The tick period must fit the 24-bit SysTick"
+        );
+        period
+    };
+
+    /// Enable the DWT and provide a new `Monotonic` ticking at `TICK_HZ`.
+    ///
+    /// See `DwtSystick::new()` for `sysclk`.
+    #[inline(always)]
+    pub fn new(dcb: &mut DCB, dwt: DWT, systick: SYST, sysclk: u32) -> Self {
+        Self::try_new(dcb, dwt, systick, sysclk).unwrap()
+    }
+
+    /// Like `new()` but return an error instead of panicking.
+    pub fn try_new(
+        dcb: &mut DCB,
+        dwt: DWT,
+        mut systick: SYST,
+        sysclk: u32,
+    ) -> Result<Self, InitError> {
+        DwtSystick::<TIMER_HZ>::check_clock(sysclk)?;
+        let mut source = DwtSource::enable(dcb, dwt)?;
+        source.set(0);
+
+        systick.set_clock_source(SystClkSource::Core);
+        systick.set_reload(Self::PERIOD - 1);
+
+        // We do not start the counters here but in `reset()`.
+
+        Ok(TickedDwtSystick {
+            source,
+            systick,
+            ticks: 0,
+            tick_start: 0,
+        })
+    }
+
+    /// Whether trace (`DEMCR.TRCENA`) was already enabled before `new()`.
+    #[inline(always)]
+    pub fn trace_was_enabled(&self) -> bool {
+        self.source.trace_was_enabled
+    }
+
+    /// Stop the monotonic and release the DWT and SysTick, see
+    /// `DwtSystick::free()`.
+    pub fn free(mut self, dcb: &mut DCB) -> (DWT, SYST) {
+        self.systick.disable_interrupt();
+        self.systick.disable_counter();
+
+        (self.source.free(dcb), self.systick)
+    }
+
+    /// The number of SysTick ticks since `reset()`.
+    #[inline(always)]
+    pub fn systick_ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    /// Cycles since `reset()` from the counted ticks and the SysTick current
+    /// value, without the cycle counter.
+    ///
+    /// This does not depend on `now()` being called and is exact at the
+    /// SysTick clock, except while a tick is pending for longer than one tick
+    /// period. A tick that elapsed but whose interrupt has not run yet is
+    /// accounted for. Inside the SysTick handler before `on_interrupt()` the
+    /// pending flag is already cleared, so the result is one period short.
+    pub fn uptime_cycles(&self) -> u64 {
+        let (pending, current) = loop {
+            let pending = SCB::is_pendst_pending();
+            let current = SYST::get_current();
+            // Retry if the SysTick wrapped between the two reads.
+            if SCB::is_pendst_pending() == pending {
+                break (pending, current);
+            }
+        };
+
+        let ticks = self.ticks + pending as u64;
+        ticks * Self::PERIOD as u64 + (Self::PERIOD - 1 - current) as u64
+    }
+}
+
+impl<const TIMER_HZ: u32, const TICK_HZ: u32> Monotonic for TickedDwtSystick<TIMER_HZ, TICK_HZ> {
+    // The tick has to keep running to count time.
+    const DISABLE_INTERRUPT_ON_EMPTY_QUEUE: bool = false;
+
+    type Instant = fugit::TimerInstantU64<TIMER_HZ>;
+    type Duration = fugit::TimerDurationU64<TIMER_HZ>;
+
+    #[inline(always)]
+    fn now(&mut self) -> Self::Instant {
+        // The offset can exceed a period while the tick interrupt is pending.
+        // `on_interrupt()` then advances `ticks` and `tick_start` together, so
+        // the time stays continuous.
+        let offset = self.source.read().wrapping_sub(self.tick_start);
+
+        Self::Instant::from_ticks(self.ticks * Self::PERIOD as u64 + offset as u64)
+    }
+
+    unsafe fn reset(&mut self) {
+        // Start both counters together so the ticks are aligned with the
+        // cycle counter epoch.
+        self.systick.clear_current();
+        self.systick.enable_counter();
+        self.source.reset();
+
+        self.ticks = 0;
+        self.tick_start = 0;
+    }
+
+    #[inline(always)]
+    fn set_compare(&mut self, _val: Self::Instant) {
+        // Deadlines are checked on every tick.
+    }
+
+    #[inline(always)]
+    fn zero() -> Self::Instant {
+        Self::Instant::from_ticks(0)
+    }
+
+    #[inline(always)]
+    fn clear_compare_flag(&mut self) {
+        // SysTick exceptions don't need flag clearing.
+    }
+
+    #[inline(always)]
+    fn on_interrupt(&mut self) {
+        self.ticks += 1;
+        self.tick_start = self.tick_start.wrapping_add(Self::PERIOD);
+    }
+}
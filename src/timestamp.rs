@@ -0,0 +1,32 @@
+//! Object-safe timestamp source
+
+use crate::{CycleSource, GenericCycleMonotonic};
+
+/// A source of timestamps usable as `&dyn TimestampSource`.
+///
+/// This lets e.g. logging code store a timestamp source without the `TIMER_HZ`
+/// parameter of the concrete monotonic. A call through `dyn` costs one
+/// indirect function call.
+pub trait TimestampSource {
+    /// The current tick count.
+    fn now_ticks(&self) -> u64;
+
+    /// The tick frequency in Hz.
+    fn hz(&self) -> u32;
+}
+
+impl<S: CycleSource, const TIMER_HZ: u32> TimestampSource for GenericCycleMonotonic<S, TIMER_HZ> {
+    /// The current tick count.
+    ///
+    /// With the `extend` feature this does not store the updated count and
+    /// relies on `now()` being called regularly, e.g. by the SysTick interrupt.
+    #[inline(always)]
+    fn now_ticks(&self) -> u64 {
+        self.scale(self.peek_ticks())
+    }
+
+    #[inline(always)]
+    fn hz(&self) -> u32 {
+        TIMER_HZ
+    }
+}